@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use log::{error, info};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_json::Value;
+
+use server_responses::*;
+
+pub type CompletionFuture<'a> = Pin<Box<dyn Future<Output = Result<String, String>> + Send + 'a>>;
+
+// What a completion is being used for, so scripted providers can answer sensibly
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompletionTask {
+    PromptIdea,
+    SimilarPrompt,
+    Hint,
+    AnswerCheck,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn system(content: impl Into<String>) -> Self {
+        Self {
+            role: "system".to_string(),
+            content: content.into(),
+        }
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self {
+            role: "user".to_string(),
+            content: content.into(),
+        }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self {
+            role: "assistant".to_string(),
+            content: content.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CompletionRequest {
+    pub task: CompletionTask,
+    pub messages: Vec<ChatMessage>,
+    pub temperature: Option<f32>,
+}
+
+// Anything that can answer a chat completion request
+pub trait CompletionProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn get_chat_completion<'a>(&'a self, request: &'a CompletionRequest) -> CompletionFuture<'a>;
+
+    // Seconds to wait between requests so we don't hit the provider's rate limit
+    fn request_cooloff_secs(&self) -> u64;
+}
+
+pub struct AzureCompletionProvider {
+    pub endpoint: String,
+    pub key: String,
+    pub request_cooloff_secs: u64,
+}
+
+impl CompletionProvider for AzureCompletionProvider {
+    fn name(&self) -> &'static str {
+        "azure"
+    }
+
+    fn get_chat_completion<'a>(&'a self, request: &'a CompletionRequest) -> CompletionFuture<'a> {
+        Box::pin(async move {
+            let mut request_body = json!({
+                "messages": request.messages,
+            });
+
+            if let Some(temperature) = request.temperature {
+                request_body["temperature"] = json!(temperature);
+            }
+
+            let response = Client::new()
+                .post(&self.endpoint)
+                .header("api-key", &self.key)
+                .json(&request_body)
+                .send()
+                .await;
+
+            get_content_from_response(response).await
+        })
+    }
+
+    fn request_cooloff_secs(&self) -> u64 {
+        self.request_cooloff_secs
+    }
+}
+
+// Works with OpenAI and any server that implements the /chat/completions API, like llama.cpp
+pub struct OpenAiCompletionProvider {
+    pub endpoint: String,
+    pub key: String,
+    pub model: String,
+    pub request_cooloff_secs: u64,
+}
+
+impl CompletionProvider for OpenAiCompletionProvider {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    fn get_chat_completion<'a>(&'a self, request: &'a CompletionRequest) -> CompletionFuture<'a> {
+        Box::pin(async move {
+            let mut request_body = json!({
+                "model": self.model,
+                "messages": request.messages,
+            });
+
+            if let Some(temperature) = request.temperature {
+                request_body["temperature"] = json!(temperature);
+            }
+
+            let mut http_request = Client::new().post(&self.endpoint).json(&request_body);
+
+            if !self.key.is_empty() {
+                http_request = http_request.bearer_auth(&self.key);
+            }
+
+            get_content_from_response(http_request.send().await).await
+        })
+    }
+
+    fn request_cooloff_secs(&self) -> u64 {
+        self.request_cooloff_secs
+    }
+}
+
+// Replies from a fixed script for each task, cycling through the responses in order
+pub struct ScriptedCompletionProvider {
+    pub responses: HashMap<CompletionTask, Vec<String>>,
+    counters: HashMap<CompletionTask, AtomicUsize>,
+}
+
+impl ScriptedCompletionProvider {
+    pub fn new(responses: HashMap<CompletionTask, Vec<String>>) -> Self {
+        let counters = responses
+            .keys()
+            .map(|task| (*task, AtomicUsize::new(0)))
+            .collect();
+
+        Self {
+            responses,
+            counters,
+        }
+    }
+}
+
+impl Default for ScriptedCompletionProvider {
+    fn default() -> Self {
+        let mut responses = HashMap::new();
+        responses.insert(
+            CompletionTask::PromptIdea,
+            vec![
+                "A labrador with antlers".to_string(),
+                "Lightning hitting a popsicle".to_string(),
+                "A caterpillar with a sword".to_string(),
+                "A can of spinach on the moon".to_string(),
+            ],
+        );
+        responses.insert(
+            CompletionTask::SimilarPrompt,
+            vec![
+                "A fuzzy deer".to_string(),
+                "Electric lollipop".to_string(),
+                "A worm holding a knife".to_string(),
+                "A tin of lettuce in space".to_string(),
+            ],
+        );
+        responses.insert(
+            CompletionTask::Hint,
+            vec![
+                "An image with something pointy has a very low value".to_string(),
+                "An electric prompt has a very high value".to_string(),
+                "Something green is worth a middling amount".to_string(),
+            ],
+        );
+        responses.insert(
+            CompletionTask::AnswerCheck,
+            vec!["Response is approved.".to_string()],
+        );
+
+        Self::new(responses)
+    }
+}
+
+impl CompletionProvider for ScriptedCompletionProvider {
+    fn name(&self) -> &'static str {
+        "scripted"
+    }
+
+    fn get_chat_completion<'a>(&'a self, request: &'a CompletionRequest) -> CompletionFuture<'a> {
+        Box::pin(async move {
+            let responses = match self.responses.get(&request.task) {
+                Some(responses) if !responses.is_empty() => responses,
+                _ => {
+                    return Err(format!("No scripted responses for task: {:?}", request.task));
+                }
+            };
+
+            let index = match self.counters.get(&request.task) {
+                Some(counter) => counter.fetch_add(1, Ordering::Relaxed),
+                None => 0,
+            };
+
+            Ok(responses[index % responses.len()].clone())
+        })
+    }
+
+    fn request_cooloff_secs(&self) -> u64 {
+        0
+    }
+}
+
+async fn get_content_from_response(
+    response: Result<reqwest::Response, reqwest::Error>,
+) -> Result<String, String> {
+    info!("Getting response back");
+
+    let error_string = match response {
+        Err(e) => format!("Failed to send request: {:?}", e),
+        Ok(returned_response) => match returned_response.json::<Value>().await {
+            Err(e) => format!("Failed to get json: {:?}", e),
+            Ok(json) => match json
+                .get("choices")
+                .and_then(|choices| choices.get(0))
+                .and_then(|data_first_element| data_first_element.get("message"))
+                .and_then(|message| message.get("content"))
+                .and_then(|content| content.as_str())
+            {
+                Some(content) => return Ok(content.to_string()),
+                None => format!("Failed to get completions message content: {:?}", json),
+            },
+        },
+    };
+
+    error!("Failed to get chat completion: {:?}", error_string);
+    Err(error_string)
+}
+
+fn get_scripted_completion_provider() -> ScriptedCompletionProvider {
+    let script_path = match env::var("SCRIPTED_COMPLETIONS_FILE") {
+        Ok(script_path) => script_path,
+        Err(_) => return ScriptedCompletionProvider::default(),
+    };
+
+    let script = fs::read_to_string(&script_path)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| {
+            serde_json::from_str::<HashMap<CompletionTask, Vec<String>>>(&contents)
+                .map_err(|e| e.to_string())
+        });
+
+    match script {
+        Ok(responses) => ScriptedCompletionProvider::new(responses),
+        Err(e) => {
+            error!(
+                "Failed to load scripted completions from {}: {}, using default script",
+                script_path, e
+            );
+            ScriptedCompletionProvider::default()
+        }
+    }
+}
+
+// Chooses the completion provider from the COMPLETION_PROVIDER environment variable (azure, openai or scripted)
+pub fn get_completion_provider(config: &GameConfig) -> Arc<dyn CompletionProvider> {
+    let default_provider = if config.debug_mode { "scripted" } else { "azure" };
+    let provider =
+        env::var("COMPLETION_PROVIDER").unwrap_or_else(|_| default_provider.to_string());
+
+    let completion_provider: Arc<dyn CompletionProvider> = match provider.to_lowercase().as_str() {
+        "openai" => Arc::new(OpenAiCompletionProvider {
+            endpoint: env::var("OPENAI_COMPLETIONS_ENDPOINT")
+                .unwrap_or_else(|_| "http://localhost:8080/v1/chat/completions".to_string()),
+            key: env::var("OPENAI_COMPLETIONS_KEY").unwrap_or_default(),
+            model: env::var("OPENAI_COMPLETIONS_MODEL").unwrap_or_else(|_| "gpt-4o-mini".to_string()),
+            request_cooloff_secs: config.prompt_gen_timeout_secs,
+        }),
+        "scripted" => Arc::new(get_scripted_completion_provider()),
+        other => {
+            if other != "azure" {
+                error!("Unknown completion provider '{}', falling back to azure", other);
+            }
+            Arc::new(AzureCompletionProvider {
+                endpoint: env::var("AZURE_AI_COMPLETIONS_ENDPOINT").unwrap_or_else(|_| {
+                    error!("Warning: AZURE_AI_COMPLETIONS_ENDPOINT is not set");
+                    String::new()
+                }),
+                key: env::var("AZURE_AI_COMPLETIONS_KEY").unwrap_or_else(|_| {
+                    error!("Warning: AZURE_AI_COMPLETIONS_KEY is not set");
+                    String::new()
+                }),
+                request_cooloff_secs: config.prompt_gen_timeout_secs,
+            })
+        }
+    };
+
+    info!("Using {} completion provider", completion_provider.name());
+
+    completion_provider
+}
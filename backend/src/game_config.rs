@@ -0,0 +1,79 @@
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::str::FromStr;
+
+use log::{error, info};
+
+use server_responses::*;
+
+fn override_from_env<T>(value: &mut T, variable_name: &str)
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Ok(variable_value) = env::var(variable_name) {
+        match variable_value.parse::<T>() {
+            Ok(parsed_value) => *value = parsed_value,
+            Err(e) => error!(
+                "Failed to parse {}='{}': {}, keeping existing value",
+                variable_name, variable_value, e
+            ),
+        }
+    }
+}
+
+// Reads the game config from the GAME_CONFIG_FILE toml file (GameConfig.toml by default),
+// then applies any ARTBABO_* environment variable overrides on top of it
+pub fn load_game_config() -> GameConfig {
+    let config_path = env::var("GAME_CONFIG_FILE").unwrap_or_else(|_| "GameConfig.toml".to_string());
+
+    let mut config = match fs::read_to_string(&config_path) {
+        Ok(contents) => match toml::from_str::<GameConfig>(&contents) {
+            Ok(config) => {
+                info!("Loaded game config from {}", config_path);
+                config
+            }
+            Err(e) => {
+                error!("Failed to parse game config {}: {}, using defaults", config_path, e);
+                GameConfig::default()
+            }
+        },
+        Err(_) => {
+            info!("No game config found at {}, using defaults", config_path);
+            GameConfig::default()
+        }
+    };
+
+    override_from_env(&mut config.debug_mode, "ARTBABO_DEBUG_MODE");
+    override_from_env(&mut config.bidding_round_time, "ARTBABO_BIDDING_ROUND_TIME");
+    override_from_env(&mut config.bidding_round_end_time, "ARTBABO_BIDDING_ROUND_END_TIME");
+    override_from_env(&mut config.end_score_screen_time, "ARTBABO_END_SCORE_SCREEN_TIME");
+    override_from_env(&mut config.loan_round_time, "ARTBABO_LOAN_ROUND_TIME");
+    override_from_env(&mut config.loan_interest_rate, "ARTBABO_LOAN_INTEREST_RATE");
+    override_from_env(&mut config.max_debt, "ARTBABO_MAX_DEBT");
+    override_from_env(&mut config.min_art_value, "ARTBABO_MIN_ART_VALUE");
+    override_from_env(&mut config.max_art_value, "ARTBABO_MAX_ART_VALUE");
+    override_from_env(&mut config.bid_increase_amount, "ARTBABO_BID_INCREASE_AMOUNT");
+    override_from_env(&mut config.bid_increase_timer_value, "ARTBABO_BID_INCREASE_TIMER_VALUE");
+    override_from_env(
+        &mut config.bid_increase_timer_start_window,
+        "ARTBABO_BID_INCREASE_TIMER_START_WINDOW",
+    );
+    override_from_env(&mut config.starting_money, "ARTBABO_STARTING_MONEY");
+    override_from_env(&mut config.force_bids_per_player, "ARTBABO_FORCE_BIDS_PER_PLAYER");
+    override_from_env(&mut config.max_players, "ARTBABO_MAX_PLAYERS");
+    override_from_env(&mut config.min_players, "ARTBABO_MIN_PLAYERS");
+    override_from_env(&mut config.image_gen_timeout_secs, "ARTBABO_IMAGE_GEN_TIMEOUT_SECS");
+    override_from_env(&mut config.prompt_gen_timeout_secs, "ARTBABO_PROMPT_GEN_TIMEOUT_SECS");
+    override_from_env(&mut config.reconnect_grace_secs, "ARTBABO_RECONNECT_GRACE_SECS");
+
+    if let Err(e) = config.validate() {
+        error!("Invalid game config: {}, using defaults", e);
+        return GameConfig::default();
+    }
+
+    info!("Using game config: {:?}", config);
+
+    config
+}
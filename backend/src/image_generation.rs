@@ -0,0 +1,218 @@
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::pin::Pin;
+use std::sync::Arc;
+
+use log::{error, info};
+use reqwest::Client;
+use serde_json::json;
+use serde_json::Value;
+
+use server_responses::*;
+
+pub type ImageGenerationFuture<'a> = Pin<Box<dyn Future<Output = Result<String, String>> + Send + 'a>>;
+
+// Anything that can turn a prompt answer into an image url the frontend can download
+pub trait ImageGenerator: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn generate_image_url<'a>(&'a self, prompt: &'a str) -> ImageGenerationFuture<'a>;
+
+    // Seconds to wait between requests so we don't hit the provider's rate limit
+    fn request_cooloff_secs(&self) -> u64;
+}
+
+pub struct AzureImageGenerator {
+    pub endpoint: String,
+    pub key: String,
+    pub request_cooloff_secs: u64,
+}
+
+impl ImageGenerator for AzureImageGenerator {
+    fn name(&self) -> &'static str {
+        "azure"
+    }
+
+    fn generate_image_url<'a>(&'a self, prompt: &'a str) -> ImageGenerationFuture<'a> {
+        Box::pin(async move {
+            let request_body = json!({
+                "prompt": prompt,
+                "n": 1,
+                "size": "1024x1024"
+            });
+
+            let response = Client::new()
+                .post(&self.endpoint)
+                .header("api-key", &self.key)
+                .json(&request_body)
+                .send()
+                .await;
+
+            get_url_from_response(response).await
+        })
+    }
+
+    fn request_cooloff_secs(&self) -> u64 {
+        self.request_cooloff_secs
+    }
+}
+
+// Works with OpenAI and any server that implements the /images/generations API
+pub struct OpenAiImageGenerator {
+    pub endpoint: String,
+    pub key: String,
+    pub model: String,
+    pub request_cooloff_secs: u64,
+}
+
+impl ImageGenerator for OpenAiImageGenerator {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    fn generate_image_url<'a>(&'a self, prompt: &'a str) -> ImageGenerationFuture<'a> {
+        Box::pin(async move {
+            let request_body = json!({
+                "model": self.model,
+                "prompt": prompt,
+                "n": 1,
+                "size": "1024x1024",
+                "response_format": "url"
+            });
+
+            let mut request = Client::new().post(&self.endpoint).json(&request_body);
+
+            if !self.key.is_empty() {
+                request = request.bearer_auth(&self.key);
+            }
+
+            get_url_from_response(request.send().await).await
+        })
+    }
+
+    fn request_cooloff_secs(&self) -> u64 {
+        self.request_cooloff_secs
+    }
+}
+
+// Picks an image from a fixed list based on the prompt, so the same prompt always gets the same image.
+// The default images are bundled in website_src/offline_art and served by the backend, so offline
+// games don't need network access beyond the game server
+pub struct OfflineImageGenerator {
+    pub image_urls: Vec<String>,
+}
+
+impl Default for OfflineImageGenerator {
+    fn default() -> Self {
+        Self {
+            image_urls: (1..=6)
+                .map(|image_number| format!("/offline_art/art_{}.png", image_number))
+                .collect(),
+        }
+    }
+}
+
+impl ImageGenerator for OfflineImageGenerator {
+    fn name(&self) -> &'static str {
+        "offline"
+    }
+
+    fn generate_image_url<'a>(&'a self, prompt: &'a str) -> ImageGenerationFuture<'a> {
+        Box::pin(async move {
+            if self.image_urls.is_empty() {
+                return Err("Offline image generator has no images configured".to_string());
+            }
+
+            let mut hasher = DefaultHasher::new();
+            prompt.hash(&mut hasher);
+            let index = (hasher.finish() % self.image_urls.len() as u64) as usize;
+
+            Ok(self.image_urls[index].clone())
+        })
+    }
+
+    fn request_cooloff_secs(&self) -> u64 {
+        0
+    }
+}
+
+async fn get_url_from_response(
+    response: Result<reqwest::Response, reqwest::Error>,
+) -> Result<String, String> {
+    let returned_response = match response {
+        Ok(returned_response) => returned_response,
+        Err(e) => {
+            error!("Failed to send request: {:?}", e);
+            return Err("Failed to send request".to_string());
+        }
+    };
+
+    info!("Sent request successfully");
+    info!("Response: {:?}", returned_response);
+
+    let json = match returned_response.json::<Value>().await {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Failed to get json: {:?}", e);
+            return Err("Failed to get json".to_string());
+        }
+    };
+
+    match json
+        .get("data")
+        .and_then(|data| data.get(0))
+        .and_then(|data_first_element| data_first_element.get("url"))
+        .and_then(|url| url.as_str())
+    {
+        Some(url) => {
+            info!("Got url: {}", url);
+            Ok(url.to_string())
+        }
+        None => {
+            error!("Failed to get url {:?}", json);
+            Err(format!("Failed to get url {}", json))
+        }
+    }
+}
+
+// Chooses the image generator from the IMAGE_GENERATOR environment variable (azure, openai or offline)
+pub fn get_image_generator(config: &GameConfig) -> Arc<dyn ImageGenerator> {
+    let default_provider = if config.debug_mode { "offline" } else { "azure" };
+    let provider = env::var("IMAGE_GENERATOR").unwrap_or_else(|_| default_provider.to_string());
+
+    let image_generator: Arc<dyn ImageGenerator> = match provider.to_lowercase().as_str() {
+        "openai" => Arc::new(OpenAiImageGenerator {
+            endpoint: env::var("OPENAI_IMAGE_ENDPOINT")
+                .unwrap_or_else(|_| "https://api.openai.com/v1/images/generations".to_string()),
+            key: env::var("OPENAI_IMAGE_KEY").unwrap_or_else(|_| {
+                error!("Warning: OPENAI_IMAGE_KEY is not set");
+                String::new()
+            }),
+            model: env::var("OPENAI_IMAGE_MODEL").unwrap_or_else(|_| "dall-e-3".to_string()),
+            request_cooloff_secs: config.image_gen_timeout_secs,
+        }),
+        "offline" => Arc::new(OfflineImageGenerator::default()),
+        other => {
+            if other != "azure" {
+                error!("Unknown image generator '{}', falling back to azure", other);
+            }
+            Arc::new(AzureImageGenerator {
+                endpoint: env::var("AZURE_AI_IMAGE_ENDPOINT").unwrap_or_else(|_| {
+                    error!("Warning: AZURE_AI_IMAGE_ENDPOINT is not set");
+                    String::new()
+                }),
+                key: env::var("AZURE_AI_IMAGE_KEY").unwrap_or_else(|_| {
+                    error!("Warning: AZURE_AI_IMAGE_KEY is not set");
+                    String::new()
+                }),
+                request_cooloff_secs: config.image_gen_timeout_secs,
+            })
+        }
+    };
+
+    info!("Using {} image generator", image_generator.name());
+
+    image_generator
+}
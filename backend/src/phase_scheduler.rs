@@ -0,0 +1,115 @@
+use std::time::Duration;
+
+use log::{error, info};
+use rocket::futures::lock::Mutex;
+use rocket::tokio;
+use std::sync::Arc;
+
+use event_work_server::EventWorkSender;
+use server_responses::*;
+
+use crate::{progress_round, send_message_to_all_players, server_time_ms, RoomList};
+
+// Each room has at most one live phase timer. Scheduling or cancelling bumps the room's
+// phase generation, and a timer only progresses the room if its generation is still current.
+
+// Sets the deadline clients count down to and starts the timer that ends the phase
+pub fn schedule_phase_end(
+    room_state: &mut RoomState,
+    room_state_list_reference: Arc<Mutex<RoomList>>,
+    net_reference: Arc<Mutex<EventWorkSender>>,
+    phase_time: u64,
+) {
+    room_state.phase_generation += 1;
+    room_state.phase_deadline_ms = Some(server_time_ms() + phase_time as i64 * 1000);
+
+    info!(
+        "Scheduling phase end for room {} in {} seconds (generation {})",
+        room_state.room_id, phase_time, room_state.phase_generation
+    );
+
+    tokio::spawn(phase_timer_task(
+        room_state.room_id as usize,
+        room_state.phase_generation,
+        room_state_list_reference,
+        net_reference,
+    ));
+}
+
+// Stops the current phase timer from firing, the phase then has no deadline
+pub fn cancel_phase_end(room_state: &mut RoomState) {
+    room_state.phase_generation += 1;
+    room_state.phase_deadline_ms = None;
+}
+
+// Moves the current phase deadline without restarting the timer
+pub fn extend_phase_end(room_state: &mut RoomState, deadline_ms: i64) {
+    if room_state.phase_deadline_ms.is_some() {
+        room_state.phase_deadline_ms = Some(deadline_ms);
+    }
+}
+
+// Waits for the room's phase deadline, which can be pushed back while waiting, then progresses the round
+async fn phase_timer_task(
+    room_id: usize,
+    generation: u64,
+    room_state_list_reference: Arc<Mutex<RoomList>>,
+    net_reference: Arc<Mutex<EventWorkSender>>,
+) {
+    loop {
+        let mut room_state_list = room_state_list_reference.lock().await;
+        let room_state = match room_state_list.get_mut(&room_id) {
+            Some(room_state) => room_state,
+            None => {
+                info!("Room {} is gone, stopping its phase timer", room_id);
+                return;
+            }
+        };
+
+        if room_state.phase_generation != generation {
+            info!(
+                "Phase timer generation {} for room {} is stale, stopping",
+                generation, room_id
+            );
+            return;
+        }
+
+        let deadline_ms = match room_state.phase_deadline_ms {
+            Some(deadline_ms) => deadline_ms,
+            None => return,
+        };
+
+        let time_left_ms = deadline_ms - server_time_ms();
+        if time_left_ms > 0 {
+            drop(room_state_list);
+            tokio::time::sleep(Duration::from_millis(time_left_ms as u64)).await;
+            continue;
+        }
+
+        progress_round(
+            room_state,
+            room_state_list_reference.clone(),
+            net_reference.clone(),
+        )
+        .await;
+
+        let room_state_clone = room_state.clone();
+        let net = net_reference.lock().await;
+
+        match send_message_to_all_players::<RoomState, EventWorkSender>(
+            &room_state_clone,
+            &room_state_clone,
+            &net,
+        )
+        .await
+        {
+            Ok(_) => info!(
+                "Updated player state for all players in room {}",
+                room_state_clone.room_id
+            ),
+            Err(e) => error!("Failed to send message: {:?}", e),
+        }
+
+        return;
+    }
+}
@@ -1,152 +1,824 @@
+use std::borrow::Cow;
 use std::pin::Pin;
-use std::{collections::HashMap, sync::Arc};
+use std::time::{Duration, Instant};
+
+use bincode::Options;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 use std::future::Future;
 
 use futures_util::stream::SplitSink;
 use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
+use ws::frame::{CloseCode, CloseFrame};
 use ws::stream::DuplexStream;
+use tungstenite::error::CapacityError;
 use ws::{result::Result, Message};
 
 use log::{error, info, warn};
 
 use async_channel;
 
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify, Semaphore};
+use tokio::task::JoinSet;
 
 pub use bevy_eventwork::{ConnectionId, NetworkMessage};
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventWorkError {
+    // No live connection has this id, usually because the client already left
+    UnknownConnection(usize),
+    Serialization(String),
+    // The send queue has shut down, nothing more can go out
+    ChannelClosed,
+    // The send queue is full right now, trying again later may work
+    ChannelFull,
+    UnknownMessageKind(String),
+    // Text frames, we only speak bincode over binary frames
+    NonBinaryMessage,
+    Socket(String),
+    // A registered message handler returned an error
+    Handler(String),
+    PacketTooLarge(usize),
+    // Over the connection's rate limit, or the limit for the given message kind
+    RateLimited(Option<String>),
+}
+
+impl std::fmt::Display for EventWorkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventWorkError::UnknownConnection(connection_id) => {
+                write!(f, "Failed to find connection with id: {}", connection_id)
+            }
+            EventWorkError::Serialization(e) => write!(f, "Failed to serialize message: {}", e),
+            EventWorkError::ChannelClosed => write!(f, "Message channel is closed"),
+            EventWorkError::ChannelFull => write!(f, "Message channel is full"),
+            EventWorkError::UnknownMessageKind(kind) => {
+                write!(f, "No handler registered for message kind: {}", kind)
+            }
+            EventWorkError::NonBinaryMessage => write!(f, "Received a non-binary message"),
+            EventWorkError::Socket(e) => write!(f, "Socket error: {}", e),
+            EventWorkError::Handler(e) => write!(f, "Message handler failed: {}", e),
+            EventWorkError::PacketTooLarge(size) => {
+                write!(f, "Packet of {} bytes is over the size limit", size)
+            }
+            EventWorkError::RateLimited(None) => write!(f, "Sending messages too fast"),
+            EventWorkError::RateLimited(Some(kind)) => {
+                write!(f, "Sending {} messages too fast", kind)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EventWorkError {}
+
+impl From<bincode::Error> for EventWorkError {
+    fn from(e: bincode::Error) -> Self {
+        EventWorkError::Serialization(e.to_string())
+    }
+}
+
+impl<T> From<async_channel::SendError<T>> for EventWorkError {
+    fn from(_: async_channel::SendError<T>) -> Self {
+        EventWorkError::ChannelClosed
+    }
+}
+
+impl<T> From<async_channel::TrySendError<T>> for EventWorkError {
+    fn from(e: async_channel::TrySendError<T>) -> Self {
+        match e {
+            async_channel::TrySendError::Full(_) => EventWorkError::ChannelFull,
+            async_channel::TrySendError::Closed(_) => EventWorkError::ChannelClosed,
+        }
+    }
+}
+
+impl From<ws::result::Error> for EventWorkError {
+    fn from(e: ws::result::Error) -> Self {
+        EventWorkError::Socket(e.to_string())
+    }
+}
+
+// Lets handlers that return String errors use ? on eventwork calls
+impl From<EventWorkError> for String {
+    fn from(e: EventWorkError) -> Self {
+        e.to_string()
+    }
+}
+
 pub trait EventWorkSendMessages {
-    fn send_message<T>(&self, connection_id: usize, message: T) -> impl std::future::Future<Output=Result<(),String>> + Send
+    fn send_message<T>(&self, connection_id: usize, message: T) -> impl std::future::Future<Output=Result<(),EventWorkError>> + Send
     where
         T: NetworkMessage;
 
-    fn broadcast<T>(&self, message: T) -> impl std::future::Future<Output=Result<(),String>> + Send
+    fn broadcast<T>(&self, message: T) -> impl std::future::Future<Output=Result<(),EventWorkError>> + Send
     where
         T: NetworkMessage;
+
+    // Sends the same already serialized packet to every listed connection
+    fn multicast(&self, connection_ids: Vec<usize>, packet: SerializedPacket) -> impl std::future::Future<Output=Result<(),EventWorkError>> + Send;
+
+    // Connections leave every group automatically when they disconnect
+    fn join_group(&self, group: &str, connection_id: usize) -> impl std::future::Future<Output=()> + Send;
+
+    fn leave_group(&self, group: &str, connection_id: usize) -> impl std::future::Future<Output=()> + Send;
+
+    fn remove_group(&self, group: &str) -> impl std::future::Future<Output=()> + Send;
+
+    fn send_to_group<T>(&self, group: &str, message: T) -> impl std::future::Future<Output=Result<(),EventWorkError>> + Send
+    where
+        T: NetworkMessage;
+}
+
+// What to do when a client sends a packet we can't handle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketPolicy {
+    Ignore,
+    // Disconnect once the connection has sent this many bad packets of the same kind
+    Strikes(u32),
+    Disconnect,
+}
+
+// A connection's strikes, counted separately for each kind of bad packet so that
+// one policy's threshold isn't used up by packets another policy let through
+#[derive(Debug, Default)]
+struct StrikeCounter {
+    malformed: u32,
+    unknown_kind: u32,
+    connection_limit: u32,
+    message_limits: HashMap<String, u32>,
+}
+
+impl StrikeCounter {
+    // Applies the policy to a bad packet and returns true if the connection should close
+    fn strike(&mut self, error: &EventWorkError, policy: PacketPolicy) -> bool {
+        let max_strikes = match policy {
+            PacketPolicy::Ignore => return false,
+            PacketPolicy::Strikes(max_strikes) => max_strikes,
+            PacketPolicy::Disconnect => return true,
+        };
+
+        let strikes = match error {
+            EventWorkError::UnknownMessageKind(_) => &mut self.unknown_kind,
+            EventWorkError::PacketTooLarge(_) | EventWorkError::RateLimited(None) => {
+                &mut self.connection_limit
+            }
+            EventWorkError::RateLimited(Some(kind)) => {
+                self.message_limits.entry(kind.clone()).or_insert(0)
+            }
+            _ => &mut self.malformed,
+        };
+
+        *strikes += 1;
+        *strikes >= max_strikes
+    }
+
+    // A packet of this kind got through its limit, so the client has slowed back down
+    fn clear_message_limit(&mut self, kind: &str) {
+        self.message_limits.remove(kind);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandlerOrdering {
+    // A connection's handlers run one at a time in the order its messages arrived
+    PerConnection,
+    // A connection's handlers can run at the same time and finish in any order
+    Unordered,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandlerDispatch {
+    // Handlers run in the read loop, so a connection's next message isn't read until they finish
+    Inline,
+    // Handlers run on their own tasks, with at most max_concurrent_handlers running across all connections
+    Pool {
+        max_concurrent_handlers: usize,
+        ordering: HandlerOrdering,
+    },
+}
+
+// How many messages a connection can have waiting for its handlers before we stop reading from it
+const HANDLER_QUEUE_SIZE: usize = 32;
+
+// Token bucket limit, lets through bursts of up to burst messages then per_second on average
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn try_take(&mut self) -> bool {
+        self.try_take_at(Instant::now())
+    }
+
+    fn try_take_at(&mut self, now: Instant) -> bool {
+        let elapsed_secs = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.tokens =
+            (self.tokens + elapsed_secs * self.limit.per_second).min(self.limit.burst as f64);
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+// Same format as bincode::deserialize, but won't read more than limit bytes
+fn deserialize_with_limit<T>(bytes: &[u8], limit: u64) -> Result<T, EventWorkError>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    Ok(bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(limit)
+        .deserialize(bytes)?)
+}
+
+// What to do when a connection's outbound queue is full because the client isn't keeping up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlowConsumerPolicy {
+    DropOldest,
+    Disconnect,
+}
+
+#[derive(Debug, Clone)]
+pub struct EventWorkConfig {
+    // Packets that fail to deserialize, and text frames
+    pub malformed_packet_policy: PacketPolicy,
+    // Packets with a kind that has no registered handler
+    pub unknown_packet_policy: PacketPolicy,
+    pub handler_dispatch: HandlerDispatch,
+    // Messages waiting to be routed to connections, senders wait when this is full
+    pub send_queue_size: usize,
+    // Messages waiting to be written to a single connection's socket
+    pub outbound_queue_size: usize,
+    pub slow_consumer_policy: SlowConsumerPolicy,
+    // How often to ping each client, None to never ping
+    pub heartbeat_interval: Option<Duration>,
+    // Disconnect clients we haven't heard anything from, pongs included, for this long
+    pub idle_timeout: Option<Duration>,
+    // Biggest binary frame we'll try to read a packet from
+    pub max_packet_size: usize,
+    // Limit on all messages from a connection, None for no limit
+    pub connection_rate_limit: Option<RateLimit>,
+    // Extra limits on single message kinds, keyed by NetworkMessage::NAME
+    pub message_rate_limits: HashMap<String, RateLimit>,
+    // Oversized packets and packets over the connection's rate limit
+    pub limit_exceeded_policy: PacketPolicy,
+    // Packets over the limit for their message kind
+    pub message_limit_exceeded_policy: PacketPolicy,
+}
+
+impl Default for EventWorkConfig {
+    fn default() -> Self {
+        Self {
+            malformed_packet_policy: PacketPolicy::Disconnect,
+            unknown_packet_policy: PacketPolicy::Strikes(3),
+            handler_dispatch: HandlerDispatch::Inline,
+            send_queue_size: 1024,
+            outbound_queue_size: 64,
+            slow_consumer_policy: SlowConsumerPolicy::DropOldest,
+            heartbeat_interval: Some(Duration::from_secs(15)),
+            idle_timeout: Some(Duration::from_secs(45)),
+            max_packet_size: 64 * 1024,
+            connection_rate_limit: Some(RateLimit {
+                burst: 50,
+                per_second: 20.0,
+            }),
+            message_rate_limits: HashMap::new(),
+            limit_exceeded_policy: PacketPolicy::Disconnect,
+            // A client going over one kind's limit now and then is usually just an eager player,
+            // one that keeps at it gets disconnected
+            message_limit_exceeded_policy: PacketPolicy::Strikes(10),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PacketRejection {
+    pub error: EventWorkError,
+    // Whether the packet policy closed the connection because of it
+    pub disconnected: bool,
+    // The packet itself when it could be read, so the server can tell the client what happened to it
+    pub packet: Option<NetworkPacket>,
 }
 
 // Taken from bevy_eventwork, made public so the server doesn't have to include bevy as a dependency
 pub enum NetworkEvent {
     Connected(ConnectionId),
     Disconnected(ConnectionId),
-    // TODO: Implement errors
-    // Error(String),
+    PacketRejected(ConnectionId, PacketRejection),
+    // Read, serialization, send and handler errors, with the connection they happened on if there was one
+    Error(Option<ConnectionId>, EventWorkError),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+async fn report_error(
+    network_event_send_channel: &async_channel::Sender<NetworkEvent>,
+    connection_id: Option<usize>,
+    error: EventWorkError,
+) {
+    let connection_id = connection_id.map(|id| ConnectionId { id: id as u32 });
+    if let Err(e) = network_event_send_channel
+        .send(NetworkEvent::Error(connection_id, error))
+        .await
+    {
+        error!("Failed to send error message: {}", e);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct NetworkPacket {
     kind: String,
     data: Vec<u8>,
 }
 
+impl NetworkPacket {
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    pub fn get_network_data<T>(&self) -> Result<T, EventWorkError>
+    where
+        T: for<'de> Deserialize<'de> + NetworkMessage,
+    {
+        // A message can't need more bytes than it was sent in
+        deserialize_with_limit(&self.data, self.data.len() as u64)
+    }
+}
+
+// A message serialized once so it can be sent to many connections
+#[derive(Clone, Debug)]
+pub struct SerializedPacket(Arc<Vec<u8>>);
+
+impl SerializedPacket {
+    pub fn new<T>(message: &T) -> Result<Self, EventWorkError>
+    where
+        T: NetworkMessage,
+    {
+        Self::with_kind(T::NAME, message)
+    }
+
+    fn with_kind<T>(kind: &str, data: &T) -> Result<Self, EventWorkError>
+    where
+        T: Serialize,
+    {
+        let packet = NetworkPacket {
+            kind: String::from(kind),
+            data: bincode::serialize(data)?,
+        };
+
+        Ok(SerializedPacket(Arc::new(bincode::serialize(&packet)?)))
+    }
+}
+
+// RPC requests carry an id from the caller, which is echoed back in the response so they can
+// match it up. Clients send an RpcRequest<Req> under Req's kind and get an RpcResponse back
+// under Resp's kind.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RpcRequest<T> {
+    pub request_id: u64,
+    pub request: T,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RpcResponse<T, E> {
+    pub request_id: u64,
+    pub result: Result<T, E>,
+}
+
+#[derive(Clone, Debug)]
+pub enum PacketTarget {
+    Connection(usize),
+    Connections(Vec<usize>),
+    // Members are looked up when the packet is sent
+    Group(String),
+    Broadcast,
+}
+
 #[derive(Clone)]
 pub struct EventWorkPacket {
-    id: usize,
-    broadcast: bool,
-    serialized_packet: Vec<u8>,
+    target: PacketTarget,
+    serialized_packet: SerializedPacket,
 }
 
 type BoxedFuture = Pin<Box<dyn Future<Output = Result<(), String>> + Send>>;
 type EventHandleFunction = dyn Fn(EventWorkSender) -> BoxedFuture + Send + Sync;
 type EventHandleFunctionStore = Arc<EventHandleFunction>;
+type GroupMap = HashMap<String, HashSet<usize>>;
+
+async fn join_group_internal(groups_reference: &Mutex<GroupMap>, group: &str, connection_id: usize) {
+    groups_reference
+        .lock()
+        .await
+        .entry(group.to_string())
+        .or_default()
+        .insert(connection_id);
+}
+
+async fn leave_group_internal(groups_reference: &Mutex<GroupMap>, group: &str, connection_id: usize) {
+    let mut groups = groups_reference.lock().await;
+    if let Some(members) = groups.get_mut(group) {
+        members.remove(&connection_id);
+        if members.is_empty() {
+            groups.remove(group);
+        }
+    }
+}
+
+async fn leave_all_groups_internal(groups_reference: &Mutex<GroupMap>, connection_id: usize) {
+    groups_reference.lock().await.retain(|_group, members| {
+        members.remove(&connection_id);
+        !members.is_empty()
+    });
+}
+
+async fn get_group_members(groups_reference: &Mutex<GroupMap>, group: &str) -> Vec<usize> {
+    match groups_reference.lock().await.get(group) {
+        Some(members) => members.iter().copied().collect(),
+        None => vec![],
+    }
+}
+
+// Applies the packet policy to a bad packet and returns true if the connection should close
+async fn reject_packet(
+    connection_id: usize,
+    error: EventWorkError,
+    packet: Option<NetworkPacket>,
+    policy: PacketPolicy,
+    strikes: &mut StrikeCounter,
+    write_reference: &Mutex<SplitSink<DuplexStream, Message>>,
+    network_event_send_channel: &async_channel::Sender<NetworkEvent>,
+) -> bool {
+    let disconnect = strikes.strike(&error, policy);
+
+    warn!(
+        "Rejected packet from connection {}: {}{}",
+        connection_id,
+        error,
+        if disconnect { ", disconnecting" } else { "" }
+    );
+
+    if disconnect {
+        let code = match error {
+            EventWorkError::Serialization(_) => CloseCode::Invalid,
+            EventWorkError::NonBinaryMessage => CloseCode::Unsupported,
+            EventWorkError::PacketTooLarge(_) => CloseCode::Size,
+            _ => CloseCode::Policy,
+        };
+
+        let close_frame = CloseFrame {
+            code,
+            reason: Cow::Owned(error.to_string()),
+        };
+
+        if let Err(e) = write_reference
+            .lock()
+            .await
+            .send(Message::Close(Some(close_frame)))
+            .await
+        {
+            warn!("Failed to send close frame to connection {}: {}", connection_id, e);
+        }
+    }
+
+    if let Err(e) = network_event_send_channel
+        .send(NetworkEvent::PacketRejected(
+            ConnectionId {
+                id: connection_id as u32,
+            },
+            PacketRejection {
+                error,
+                disconnected: disconnect,
+                packet,
+            },
+        ))
+        .await
+    {
+        error!("Failed to send packet rejected message: {}", e);
+    }
+
+    disconnect
+}
+
+// Each handler gets its own task so a panic only loses that one message
+// instead of taking down the read loop or the connection's handler queue
+async fn run_handler(function: EventHandleFunctionStore, eventwork_sender: EventWorkSender) {
+    let connection_id = eventwork_sender.connection_id;
+    let network_event_send_channel = eventwork_sender.network_event_send_channel.clone();
+
+    let error = match tokio::spawn(async move { function(eventwork_sender).await }).await {
+        Ok(Ok(())) => return,
+        Ok(Err(e)) => e,
+        Err(e) => format!("Handler panicked: {}", e),
+    };
+
+    error!("User defined function encountered an error:");
+    error!("{}", error);
+    report_error(
+        &network_event_send_channel,
+        Some(connection_id),
+        EventWorkError::Handler(error),
+    )
+    .await;
+}
 
+// Hands a connection's messages to their handlers according to the dispatch config
+enum HandlerDispatcher {
+    Inline,
+    Ordered {
+        handler_queue: async_channel::Sender<(EventHandleFunctionStore, EventWorkSender)>,
+        queue_task: tokio::task::JoinHandle<()>,
+    },
+    Unordered {
+        handler_semaphore: Arc<Semaphore>,
+        handler_tasks: JoinSet<()>,
+    },
+}
+
+impl HandlerDispatcher {
+    fn new(handler_dispatch: HandlerDispatch, handler_semaphore: Arc<Semaphore>) -> Self {
+        match handler_dispatch {
+            HandlerDispatch::Inline => HandlerDispatcher::Inline,
+            HandlerDispatch::Pool {
+                ordering: HandlerOrdering::PerConnection,
+                ..
+            } => {
+                let (handler_queue, handler_queue_receiver) =
+                    async_channel::bounded(HANDLER_QUEUE_SIZE);
+
+                let queue_task = tokio::spawn(async move {
+                    while let Ok((function, eventwork_sender)) = handler_queue_receiver.recv().await
+                    {
+                        let _permit = match handler_semaphore.acquire().await {
+                            Ok(permit) => permit,
+                            Err(_) => return,
+                        };
+                        run_handler(function, eventwork_sender).await;
+                    }
+                });
+
+                HandlerDispatcher::Ordered {
+                    handler_queue,
+                    queue_task,
+                }
+            }
+            HandlerDispatch::Pool {
+                ordering: HandlerOrdering::Unordered,
+                ..
+            } => HandlerDispatcher::Unordered {
+                handler_semaphore,
+                handler_tasks: JoinSet::new(),
+            },
+        }
+    }
+
+    async fn dispatch(&mut self, function: EventHandleFunctionStore, eventwork_sender: EventWorkSender) {
+        match self {
+            HandlerDispatcher::Inline => run_handler(function, eventwork_sender).await,
+            HandlerDispatcher::Ordered { handler_queue, .. } => {
+                if handler_queue.send((function, eventwork_sender)).await.is_err() {
+                    error!("Handler queue closed, dropping message");
+                }
+            }
+            HandlerDispatcher::Unordered {
+                handler_semaphore,
+                handler_tasks,
+            } => {
+                // Waiting for a permit here stops us reading more than the pool can take
+                let permit = match Arc::clone(handler_semaphore).acquire_owned().await {
+                    Ok(permit) => permit,
+                    Err(_) => return,
+                };
+
+                // Clear out finished tasks so the set doesn't grow for long lived connections
+                while handler_tasks.try_join_next().is_some() {}
+
+                handler_tasks.spawn(async move {
+                    run_handler(function, eventwork_sender).await;
+                    drop(permit);
+                });
+            }
+        }
+    }
+
+    // Waits for every message already read to be handled
+    async fn finish(self) {
+        match self {
+            HandlerDispatcher::Inline => {}
+            HandlerDispatcher::Ordered {
+                handler_queue,
+                queue_task,
+            } => {
+                handler_queue.close();
+                if let Err(e) = queue_task.await {
+                    error!("Handler queue task failed: {}", e);
+                }
+            }
+            HandlerDispatcher::Unordered {
+                mut handler_tasks, ..
+            } => while handler_tasks.join_next().await.is_some() {},
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct EventWorkSender {
     pub packet_input: NetworkPacket,
     pub message_send_channel: async_channel::Sender<EventWorkPacket>,
+    pub groups_reference: Arc<Mutex<GroupMap>>,
+    pub network_event_send_channel: async_channel::Sender<NetworkEvent>,
     pub connection_id: usize,
 }
 
 impl EventWorkSender {
-    pub fn get_network_data<T>(&self) -> Result<T, String>
+    pub fn get_network_data<T>(&self) -> Result<T, EventWorkError>
     where
         T: for<'de> Deserialize<'de> + NetworkMessage,
     {
-        match bincode::deserialize(&self.packet_input.data) {
-            Ok(data) => Ok(data),
-            Err(e) => Err(e.to_string()),
-        }
+        self.packet_input.get_network_data()
     }
 
-    fn from_message_to_packet<T>(
-        connection_id: usize,
-        broadcast: bool,
-        message: T,
-    ) -> EventWorkPacket
+    // Serializes the message and queues it for the send task
+    async fn queue_message<T>(&self, target: PacketTarget, message: T) -> Result<(), EventWorkError>
     where
         T: NetworkMessage,
     {
-        let packet = NetworkPacket {
-            kind: String::from(T::NAME),
-            data: bincode::serialize(&message).unwrap(),
+        let serialized_packet = match SerializedPacket::new(&message) {
+            Ok(serialized_packet) => serialized_packet,
+            Err(e) => {
+                let connection_id = match target {
+                    PacketTarget::Connection(connection_id) => Some(connection_id),
+                    _ => None,
+                };
+                report_error(&self.network_event_send_channel, connection_id, e.clone()).await;
+                return Err(e);
+            }
         };
 
-        let serialized_packet = bincode::serialize(&packet).unwrap();
-
-        EventWorkPacket {
-            id: connection_id,
-            broadcast,
-            serialized_packet,
-        }
+        self.message_send_channel
+            .send(EventWorkPacket {
+                target,
+                serialized_packet,
+            })
+            .await?;
+        Ok(())
     }
 }
 
 impl EventWorkSendMessages for EventWorkSender {
-    async fn send_message<T>(&self, connection_id: usize, message: T) -> Result<(), String>
+    async fn send_message<T>(&self, connection_id: usize, message: T) -> Result<(), EventWorkError>
     where
         T: NetworkMessage,
     {
-        let eventwork_packet = Self::from_message_to_packet(connection_id, false, message);
-
-        match self.message_send_channel.send(eventwork_packet).await {
-            Ok(_) => {
-                return Ok(());
-            }
-            Err(e) => {
-                return Err(e.to_string());
-            }
-        }
+        self.queue_message(PacketTarget::Connection(connection_id), message).await
     }
 
-    async fn broadcast<T>(&self, message: T) -> Result<(), String>
+    async fn broadcast<T>(&self, message: T) -> Result<(), EventWorkError>
     where
         T: NetworkMessage,
     {
-        let eventwork_packet = Self::from_message_to_packet(0, true, message);
+        self.queue_message(PacketTarget::Broadcast, message).await
+    }
 
-        match self.message_send_channel.send(eventwork_packet).await {
-            Ok(_) => {
-                return Ok(());
-            }
-            Err(e) => {
-                return Err(e.to_string());
-            }
-        }
+    async fn multicast(
+        &self,
+        connection_ids: Vec<usize>,
+        packet: SerializedPacket,
+    ) -> Result<(), EventWorkError> {
+        self.message_send_channel
+            .send(EventWorkPacket {
+                target: PacketTarget::Connections(connection_ids),
+                serialized_packet: packet,
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn join_group(&self, group: &str, connection_id: usize) {
+        join_group_internal(&self.groups_reference, group, connection_id).await;
+    }
+
+    async fn leave_group(&self, group: &str, connection_id: usize) {
+        leave_group_internal(&self.groups_reference, group, connection_id).await;
+    }
+
+    async fn remove_group(&self, group: &str) {
+        self.groups_reference.lock().await.remove(group);
+    }
+
+    async fn send_to_group<T>(&self, group: &str, message: T) -> Result<(), EventWorkError>
+    where
+        T: NetworkMessage,
+    {
+        self.queue_message(PacketTarget::Group(group.to_string()), message).await
     }
 }
 
 struct EventWorkConnection {
     id: usize,
     handle_packet_task: Arc<dyn Fn() -> BoxedFuture + Send + Sync>,
-    write_reference: Arc<Mutex<SplitSink<DuplexStream, Message>>>,
+    // Drained by the connection's own writer task, so a slow socket only holds up itself
+    outbound_queue: async_channel::Sender<SerializedPacket>,
+    // Wakes the read loop to end the connection
+    close_signal: Arc<Notify>,
+    slow_consumer_policy: SlowConsumerPolicy,
 }
 
 impl EventWorkConnection {
-    pub async fn send_message(&self, message: EventWorkPacket) -> Result<(), String> {
-        match self
-            .write_reference
-            .lock()
-            .await
-            .send(Message::Binary(message.serialized_packet))
-            .await
-        {
-            Ok(_) => {
-                return Ok(());
-            }
-            Err(e) => {
-                return Err(e.to_string());
-            }
+    // Queues the packet without waiting, so it's fine to call while holding the connection map
+    fn send_message(&self, packet: &SerializedPacket) -> Result<(), EventWorkError> {
+        match self.outbound_queue.try_send(packet.clone()) {
+            Ok(_) => Ok(()),
+            Err(async_channel::TrySendError::Full(packet)) => match self.slow_consumer_policy {
+                SlowConsumerPolicy::DropOldest => {
+                    warn!("Connection {} is falling behind, dropping its oldest message", self.id);
+                    self.outbound_queue.force_send(packet)?;
+                    Ok(())
+                }
+                SlowConsumerPolicy::Disconnect => {
+                    warn!("Connection {} is falling behind, disconnecting", self.id);
+                    self.outbound_queue.close();
+                    self.close_signal.notify_one();
+                    Err(EventWorkError::ChannelFull)
+                }
+            },
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+// Finishes after the duration, or never if there isn't one
+async fn sleep_for(duration: Option<Duration>) {
+    match duration {
+        Some(duration) => tokio::time::sleep(duration).await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn next_tick(interval: &mut Option<tokio::time::Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+async fn connection_writer_task(
+    connection_id: usize,
+    outbound_queue: async_channel::Receiver<SerializedPacket>,
+    write_reference: Arc<Mutex<SplitSink<DuplexStream, Message>>>,
+    close_signal: Arc<Notify>,
+    heartbeat_interval: Option<Duration>,
+    network_event_send_channel: async_channel::Sender<NetworkEvent>,
+) {
+    let mut heartbeat = heartbeat_interval.map(|heartbeat_interval| {
+        tokio::time::interval_at(
+            tokio::time::Instant::now() + heartbeat_interval,
+            heartbeat_interval,
+        )
+    });
+
+    loop {
+        // Clients answer pings with pongs, which keeps them inside the idle timeout
+        let message = tokio::select! {
+            packet = outbound_queue.recv() => match packet {
+                Ok(packet) => Message::Binary(packet.0.to_vec()),
+                Err(_) => break,
+            },
+            _ = next_tick(&mut heartbeat) => Message::Ping(vec![]),
+        };
+
+        let result = write_reference.lock().await.send(message).await;
+
+        if let Err(e) = result {
+            // The socket is gone, stop reading from it too
+            report_error(&network_event_send_channel, Some(connection_id), e.into()).await;
+            close_signal.notify_one();
+            break;
         }
     }
 }
@@ -155,6 +827,10 @@ pub struct EventWorkServer {
     event_map_reference: Arc<Mutex<HashMap<String, EventHandleFunctionStore>>>,
     connection_counter: usize,
     active_connections_reference: Arc<Mutex<HashMap<usize, EventWorkConnection>>>,
+    groups_reference: Arc<Mutex<GroupMap>>,
+    // Shared by every connection so the handler pool limit is server wide
+    handler_semaphore: Arc<Semaphore>,
+    config: EventWorkConfig,
     tx_message_send_channel: async_channel::Sender<EventWorkPacket>,
     tx_message_receive_channel: async_channel::Receiver<EventWorkPacket>,
     network_event_send_channel: async_channel::Sender<NetworkEvent>,
@@ -165,13 +841,27 @@ pub struct EventWorkServer {
 
 impl EventWorkServer {
     pub fn default() -> Self {
-        let (send, receive) = async_channel::unbounded();
+        Self::with_config(EventWorkConfig::default())
+    }
+
+    pub fn with_config(config: EventWorkConfig) -> Self {
+        let (send, receive) = async_channel::bounded(config.send_queue_size.max(1));
+        let max_concurrent_handlers = match config.handler_dispatch {
+            HandlerDispatch::Inline => 1,
+            HandlerDispatch::Pool {
+                max_concurrent_handlers,
+                ..
+            } => max_concurrent_handlers.max(1),
+        };
         let (close_send, close_receive) = async_channel::unbounded();
 
         Self {
             event_map_reference: Arc::new(Mutex::new(HashMap::default())),
             connection_counter: 0,
             active_connections_reference: Arc::new(Mutex::new(HashMap::default())),
+            groups_reference: Arc::new(Mutex::new(HashMap::default())),
+            handler_semaphore: Arc::new(Semaphore::new(max_concurrent_handlers)),
+            config,
             tx_message_send_channel: send,
             tx_message_receive_channel: receive,
             network_event_send_channel: close_send,
@@ -180,36 +870,63 @@ impl EventWorkServer {
         }
     }
 
+    // Config for the websocket upgrade, so oversized frames are turned away before they're buffered
+    pub fn websocket_config(&self) -> ws::Config {
+        ws::Config {
+            max_message_size: Some(self.config.max_packet_size),
+            max_frame_size: Some(self.config.max_packet_size),
+            ..Default::default()
+        }
+    }
+
     pub async fn init(&self) {
         // Spawn thread for handling message send requests
         let tx_message_receive_channel = self.tx_message_receive_channel.clone();
         let active_connections_reference = Arc::clone(&self.active_connections_reference);
+        let groups_reference = Arc::clone(&self.groups_reference);
+        let network_event_send_channel = self.network_event_send_channel.clone();
         tokio::spawn(async move {
             while let Ok(eventwork_packet) = tx_message_receive_channel.recv().await {
-                if eventwork_packet.broadcast {
-                    match Self::broadcast_message_internal(
-                        active_connections_reference.clone(),
-                        eventwork_packet,
-                    )
-                    .await
-                    {
-                        Ok(_) => {}
-                        Err(e) => {
-                            error!("Failed to broadcast message: {}", e);
-                        }
-                    };
-                } else {
-                    match Self::send_message_internal(
-                        active_connections_reference.clone(),
-                        eventwork_packet,
-                    )
-                    .await
-                    {
-                        Ok(_) => {}
-                        Err(e) => {
-                            error!("Failed to send message: {}", e);
-                        }
-                    };
+                let result = match eventwork_packet.target {
+                    PacketTarget::Connection(connection_id) => {
+                        Self::send_message_internal(
+                            active_connections_reference.clone(),
+                            connection_id,
+                            &eventwork_packet.serialized_packet,
+                            &network_event_send_channel,
+                        )
+                        .await
+                    }
+                    PacketTarget::Connections(connection_ids) => {
+                        Self::multicast_message_internal(
+                            active_connections_reference.clone(),
+                            &connection_ids,
+                            &eventwork_packet.serialized_packet,
+                            &network_event_send_channel,
+                        )
+                        .await
+                    }
+                    PacketTarget::Group(group) => {
+                        Self::multicast_message_internal(
+                            active_connections_reference.clone(),
+                            &get_group_members(&groups_reference, &group).await,
+                            &eventwork_packet.serialized_packet,
+                            &network_event_send_channel,
+                        )
+                        .await
+                    }
+                    PacketTarget::Broadcast => {
+                        Self::broadcast_message_internal(
+                            active_connections_reference.clone(),
+                            &eventwork_packet.serialized_packet,
+                            &network_event_send_channel,
+                        )
+                        .await
+                    }
+                };
+
+                if let Err(e) = result {
+                    error!("Failed to send message: {}", e);
                 }
             }
         });
@@ -217,6 +934,7 @@ impl EventWorkServer {
         // Spawn thread for handling network event requests
         let network_event_receive_channel = self.network_event_receive_channel.clone();
         let active_connections_reference_clone = Arc::clone(&self.active_connections_reference);
+        let groups_reference_clone = Arc::clone(&self.groups_reference);
         let network_event_function_option_reference =
             self.network_event_function_option_reference.clone();
         tokio::spawn(async move {
@@ -225,6 +943,7 @@ impl EventWorkServer {
                     let connection_id = event_connection_id.id as usize;
                     let mut active_connections = active_connections_reference_clone.lock().await;
                     active_connections.remove(&connection_id);
+                    leave_all_groups_internal(&groups_reference_clone, connection_id).await;
                     info!("Removed connection with id: {}", connection_id);
                 }
 
@@ -259,116 +978,335 @@ impl EventWorkServer {
 
     async fn send_message_internal(
         active_connections_reference: Arc<Mutex<HashMap<usize, EventWorkConnection>>>,
-        eventwork_packet: EventWorkPacket,
-    ) -> Result<(), String> {
+        connection_id: usize,
+        packet: &SerializedPacket,
+        network_event_send_channel: &async_channel::Sender<NetworkEvent>,
+    ) -> Result<(), EventWorkError> {
+        let result = match active_connections_reference.lock().await.get(&connection_id) {
+            Some(connection) => connection.send_message(packet),
+            None => Err(EventWorkError::UnknownConnection(connection_id)),
+        };
+
+        if let Err(e) = &result {
+            report_error(network_event_send_channel, Some(connection_id), e.clone()).await;
+        }
+
+        result
+    }
+
+    // Tries every connection even if some fail, and returns the first failure
+    async fn multicast_message_internal(
+        active_connections_reference: Arc<Mutex<HashMap<usize, EventWorkConnection>>>,
+        connection_ids: &[usize],
+        packet: &SerializedPacket,
+        network_event_send_channel: &async_channel::Sender<NetworkEvent>,
+    ) -> Result<(), EventWorkError> {
         let active_connections = active_connections_reference.lock().await;
-        match active_connections.get(&eventwork_packet.id) {
-            Some(connection) => match connection.send_message(eventwork_packet).await {
-                Ok(_) => {}
-                Err(e) => {
-                    return Err(e.to_string());
-                }
-            },
-            None => {
-                return Err(format!(
-                    "Failed to find connection with id: {}",
-                    eventwork_packet.id
-                ));
+        let mut first_error = None;
+
+        for connection_id in connection_ids {
+            let result = match active_connections.get(connection_id) {
+                Some(connection) => connection.send_message(packet),
+                None => Err(EventWorkError::UnknownConnection(*connection_id)),
+            };
+
+            if let Err(e) = result {
+                warn!("Failed to send message to connection {}: {}", connection_id, e);
+                report_error(network_event_send_channel, Some(*connection_id), e.clone()).await;
+                first_error.get_or_insert(e);
             }
         }
-        Ok(())
+
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
     }
 
     async fn broadcast_message_internal(
         active_connections_reference: Arc<Mutex<HashMap<usize, EventWorkConnection>>>,
-        eventwork_packet: EventWorkPacket,
-    ) -> Result<(), String> {
-        let active_connections = active_connections_reference.lock().await;
-        for connection in active_connections.values() {
-            match connection.send_message(eventwork_packet.clone()).await {
-                Ok(_) => {}
-                Err(e) => {
-                    return Err(format!("Failed to send message: {}", e));
-                }
+        packet: &SerializedPacket,
+        network_event_send_channel: &async_channel::Sender<NetworkEvent>,
+    ) -> Result<(), EventWorkError> {
+        let connection_ids: Vec<usize> = active_connections_reference
+            .lock()
+            .await
+            .keys()
+            .copied()
+            .collect();
+
+        Self::multicast_message_internal(
+            active_connections_reference,
+            &connection_ids,
+            packet,
+            network_event_send_channel,
+        )
+        .await
+    }
+
+    async fn serialize_message<T>(
+        &self,
+        connection_id: Option<usize>,
+        message: &T,
+    ) -> Result<SerializedPacket, EventWorkError>
+    where
+        T: NetworkMessage,
+    {
+        match SerializedPacket::new(message) {
+            Ok(packet) => Ok(packet),
+            Err(e) => {
+                report_error(&self.network_event_send_channel, connection_id, e.clone()).await;
+                Err(e)
             }
         }
-        Ok(())
     }
 
     pub async fn handle_new_connection(
         &mut self,
         stream: DuplexStream,
-    ) -> Result<Arc<dyn Fn() -> BoxedFuture + Send + Sync>, String> {
+    ) -> Result<Arc<dyn Fn() -> BoxedFuture + Send + Sync>, EventWorkError> {
         let (write, read) = stream.split();
 
         let tx_message_send_channel = self.tx_message_send_channel.clone();
+        let groups_reference = Arc::clone(&self.groups_reference);
         let network_event_send_channel = self.network_event_send_channel.clone();
 
         let read_reference = Arc::new(Mutex::new(read));
         let write_reference = Arc::new(Mutex::new(write));
         let event_map_reference = Arc::clone(&self.event_map_reference);
+        let config = self.config.clone();
+        let handler_semaphore = Arc::clone(&self.handler_semaphore);
+        let packet_write_reference = Arc::clone(&write_reference);
+        let packet_network_event_send_channel = network_event_send_channel.clone();
+        let close_signal = Arc::new(Notify::new());
+        let packet_close_signal = Arc::clone(&close_signal);
 
         let connection_id = self.connection_counter;
 
+        let (outbound_queue, outbound_queue_receiver) =
+            async_channel::bounded(self.config.outbound_queue_size.max(1));
+        tokio::spawn(connection_writer_task(
+            connection_id,
+            outbound_queue_receiver,
+            Arc::clone(&write_reference),
+            Arc::clone(&close_signal),
+            self.config.heartbeat_interval,
+            network_event_send_channel.clone(),
+        ));
+
         let new_connection = EventWorkConnection {
             id: connection_id,
             handle_packet_task: Arc::new(move || {
                 let read_reference_clone = Arc::clone(&read_reference);
+                let write_reference_clone = Arc::clone(&packet_write_reference);
+                let network_event_send_channel_clone = packet_network_event_send_channel.clone();
+                let config_clone = config.clone();
+                let handler_semaphore_clone = Arc::clone(&handler_semaphore);
+                let close_signal_clone = Arc::clone(&packet_close_signal);
                 let event_map_reference_clone = Arc::clone(&event_map_reference);
                 let tx_message_send_channel_clone = tx_message_send_channel.clone();
+                let groups_reference_clone = Arc::clone(&groups_reference);
                 let connection_id_clone = connection_id;
                 Box::pin(async move {
-                    while let Some(message) = read_reference_clone.lock().await.next().await {
+                    let mut strikes = StrikeCounter::default();
+                    let mut connection_bucket =
+                        config_clone.connection_rate_limit.map(TokenBucket::new);
+                    let mut message_buckets: HashMap<String, TokenBucket> = HashMap::new();
+                    let mut handler_dispatcher = HandlerDispatcher::new(
+                        config_clone.handler_dispatch,
+                        handler_semaphore_clone,
+                    );
+
+                    loop {
+                        // Only hold the read lock while waiting for the next message
+                        let message_option = tokio::select! {
+                            message_option = async {
+                                read_reference_clone.lock().await.next().await
+                            } => message_option,
+                            _ = close_signal_clone.notified() => {
+                                info!("Closing connection {}", connection_id_clone);
+                                None
+                            }
+                            _ = sleep_for(config_clone.idle_timeout) => {
+                                info!(
+                                    "Connection {} went quiet for too long, closing it",
+                                    connection_id_clone
+                                );
+                                None
+                            }
+                        };
+
+                        let message = match message_option {
+                            Some(message) => message,
+                            None => break,
+                        };
+
                         let message_val = match message {
                             Ok(message) => message,
+                            Err(ws::result::Error::Capacity(CapacityError::MessageTooLong {
+                                size,
+                                ..
+                            })) => {
+                                // The rest of the frame was never read, so the stream can't be used after this
+                                reject_packet(
+                                    connection_id_clone,
+                                    EventWorkError::PacketTooLarge(size),
+                                    None,
+                                    PacketPolicy::Disconnect,
+                                    &mut strikes,
+                                    &write_reference_clone,
+                                    &network_event_send_channel_clone,
+                                )
+                                .await;
+                                break;
+                            }
                             Err(e) => {
                                 warn!("Hit a non-fatal error: {:?}", e);
+                                report_error(
+                                    &network_event_send_channel_clone,
+                                    Some(connection_id_clone),
+                                    EventWorkError::from(e),
+                                )
+                                .await;
                                 continue;
                             }
                         };
 
-                        let packet = match message_val.clone() {
+                        let packet_result = match message_val {
                             ws::Message::Binary(binary) => {
-                                match bincode::deserialize::<NetworkPacket>(&binary) {
-                                    Ok(packet) => packet,
-                                    Err(e) => {
-                                        error!("Error deserializing packet: {:?}", e);
-                                        break;
-                                    }
+                                if binary.len() > config_clone.max_packet_size {
+                                    Err(EventWorkError::PacketTooLarge(binary.len()))
+                                } else if !connection_bucket
+                                    .as_mut()
+                                    .is_none_or(|bucket| bucket.try_take())
+                                {
+                                    Err(EventWorkError::RateLimited(None))
+                                } else {
+                                    deserialize_with_limit::<NetworkPacket>(
+                                        &binary,
+                                        config_clone.max_packet_size as u64,
+                                    )
                                 }
                             }
-                            _ => {
-                                error!("Received non-binary message: {}", message_val);
+                            ws::Message::Text(_) => Err(EventWorkError::NonBinaryMessage),
+                            // Pongs for pings are sent by the websocket library
+                            ws::Message::Ping(_) | ws::Message::Pong(_) | ws::Message::Frame(_) => {
+                                continue;
+                            }
+                            ws::Message::Close(close_frame) => {
+                                info!(
+                                    "Connection {} sent a close frame: {:?}",
+                                    connection_id_clone, close_frame
+                                );
                                 break;
                             }
                         };
 
+                        // Only count packets that name a kind towards that kind's limit,
+                        // and hold on to the ones over it so the rejection can include them
+                        let (packet_result, rejected_packet) = match packet_result {
+                            Ok(packet) => {
+                                let within_limit =
+                                    match config_clone.message_rate_limits.get(&packet.kind) {
+                                        Some(limit) => {
+                                            let within_limit = message_buckets
+                                                .entry(packet.kind.clone())
+                                                .or_insert_with(|| TokenBucket::new(*limit))
+                                                .try_take();
+                                            if within_limit {
+                                                strikes.clear_message_limit(&packet.kind);
+                                            }
+                                            within_limit
+                                        }
+                                        None => true,
+                                    };
+
+                                if within_limit {
+                                    (Ok(packet), None)
+                                } else {
+                                    let kind = packet.kind.clone();
+                                    (Err(EventWorkError::RateLimited(Some(kind))), Some(packet))
+                                }
+                            }
+                            Err(e) => (Err(e), None),
+                        };
+
+                        let packet = match packet_result {
+                            Ok(packet) => packet,
+                            Err(e) => {
+                                let policy = match e {
+                                    EventWorkError::RateLimited(Some(_)) => {
+                                        config_clone.message_limit_exceeded_policy
+                                    }
+                                    EventWorkError::PacketTooLarge(_)
+                                    | EventWorkError::RateLimited(None) => {
+                                        config_clone.limit_exceeded_policy
+                                    }
+                                    _ => config_clone.malformed_packet_policy,
+                                };
+
+                                if reject_packet(
+                                    connection_id_clone,
+                                    e,
+                                    rejected_packet,
+                                    policy,
+                                    &mut strikes,
+                                    &write_reference_clone,
+                                    &network_event_send_channel_clone,
+                                )
+                                .await
+                                {
+                                    break;
+                                }
+                                continue;
+                            }
+                        };
+
                         // Handle packet code
-                        let function = {
-                            let event_map = event_map_reference_clone.lock().await;
-                            match event_map.get(&packet.kind) {
-                                Some(function) => function.clone(),
-                                None => {
-                                    error!("Received packet with unknown kind: {}", packet.kind);
+                        let function_option = event_map_reference_clone
+                            .lock()
+                            .await
+                            .get(&packet.kind)
+                            .cloned();
+
+                        let function = match function_option {
+                            Some(function) => function,
+                            None => {
+                                if reject_packet(
+                                    connection_id_clone,
+                                    EventWorkError::UnknownMessageKind(packet.kind.clone()),
+                                    Some(packet),
+                                    config_clone.unknown_packet_policy,
+                                    &mut strikes,
+                                    &write_reference_clone,
+                                    &network_event_send_channel_clone,
+                                )
+                                .await
+                                {
                                     break;
                                 }
+                                continue;
                             }
                         };
 
                         let eventwork_sender = EventWorkSender {
                             packet_input: packet,
                             message_send_channel: tx_message_send_channel_clone.clone(),
+                            groups_reference: Arc::clone(&groups_reference_clone),
+                            network_event_send_channel: network_event_send_channel_clone.clone(),
                             connection_id: connection_id_clone,
                         };
-                        if let Err(e) = function(eventwork_sender).await {
-                            error!("User defined function encountered an error:");
-                            error!("{}", e);
-                        }
+                        handler_dispatcher.dispatch(function, eventwork_sender).await;
                     }
+
+                    // Let handlers for messages that already arrived run before the disconnect goes out
+                    handler_dispatcher.finish().await;
                     Ok(())
                 })
             }),
-            write_reference: Arc::clone(&write_reference),
+            outbound_queue,
+            close_signal,
+            slow_consumer_policy: self.config.slow_consumer_policy,
         };
 
         self.connection_counter += 1;
@@ -420,7 +1358,7 @@ impl EventWorkServer {
     pub async fn register_message<T>(
         &self,
         input_function: EventHandleFunctionStore,
-    ) -> Result<(), Box<dyn std::error::Error>>
+    ) -> Result<(), EventWorkError>
     where
         T: NetworkMessage,
     {
@@ -428,33 +1366,265 @@ impl EventWorkServer {
         event_map.insert(String::from(T::NAME), input_function);
         Ok(())
     }
+
+    // Like register_message, but the handler gets the message already deserialized
+    // along with its own clone of the state
+    pub async fn route<T, S, F, Fut>(&self, state: S, handler: F) -> Result<(), EventWorkError>
+    where
+        T: NetworkMessage,
+        S: Clone + Send + Sync + 'static,
+        F: Fn(EventWorkSender, T, S) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        self.register_message::<T>(Arc::new(move |sender: EventWorkSender| {
+            let message = sender.get_network_data::<T>();
+            let handler_future = message.map(|message| handler(sender, message, state.clone()));
+            Box::pin(async move {
+                match handler_future {
+                    Ok(handler_future) => handler_future.await,
+                    Err(e) => Err(format!("Failed to get network data: {}", e)),
+                }
+            })
+        }))
+        .await
+    }
+
+    // Like route, but the handler's result goes back to the connection that asked, tagged with
+    // its request id. Requests that can't be read or whose handler panics get an error back too,
+    // as long as their request id could be read.
+    pub async fn register_rpc<Req, Resp, E, S, F, Fut>(
+        &self,
+        state: S,
+        handler: F,
+    ) -> Result<(), EventWorkError>
+    where
+        Req: NetworkMessage,
+        Resp: NetworkMessage,
+        E: Serialize + From<EventWorkError> + Send + 'static,
+        S: Clone + Send + Sync + 'static,
+        F: Fn(EventWorkSender, Req, S) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Resp, E>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        self.register_message::<Req>(Arc::new(move |sender: EventWorkSender| {
+            let handler = Arc::clone(&handler);
+            let state = state.clone();
+            Box::pin(async move {
+                let data = &sender.packet_input.data;
+                let rpc_request =
+                    deserialize_with_limit::<RpcRequest<Req>>(data, data.len() as u64);
+
+                let (request_id, result) = match rpc_request {
+                    Ok(rpc_request) => {
+                        let handler_future =
+                            handler(sender.clone(), rpc_request.request, state);
+                        let result = match tokio::spawn(handler_future).await {
+                            Ok(result) => result,
+                            Err(e) => Err(E::from(EventWorkError::Handler(format!(
+                                "Handler panicked: {}",
+                                e
+                            )))),
+                        };
+                        (rpc_request.request_id, result)
+                    }
+                    Err(e) => {
+                        // The request id comes first, so it can often still be read
+                        let request_id = deserialize_with_limit::<u64>(data, data.len() as u64)
+                            .map_err(|_| e.clone())?;
+                        (request_id, Err(E::from(e)))
+                    }
+                };
+
+                let packet =
+                    SerializedPacket::with_kind(Resp::NAME, &RpcResponse { request_id, result })?;
+                sender.multicast(vec![sender.connection_id], packet).await?;
+                Ok(())
+            })
+        }))
+        .await
+    }
 }
 
 impl EventWorkSendMessages for EventWorkServer {
-    async fn send_message<T>(&self, connection_id: usize, message: T) -> Result<(), String>
+    async fn send_message<T>(&self, connection_id: usize, message: T) -> Result<(), EventWorkError>
     where
         T: NetworkMessage,
     {
-        let eventwork_packet =
-            EventWorkSender::from_message_to_packet::<T>(connection_id, false, message);
+        let packet = self.serialize_message(Some(connection_id), &message).await?;
 
         Self::send_message_internal(
             Arc::clone(&self.active_connections_reference),
-            eventwork_packet,
+            connection_id,
+            &packet,
+            &self.network_event_send_channel,
         )
         .await
     }
 
-    async fn broadcast<T>(&self, message: T) -> Result<(), String>
+    async fn broadcast<T>(&self, message: T) -> Result<(), EventWorkError>
     where
         T: NetworkMessage,
     {
-        let eventwork_packet = EventWorkSender::from_message_to_packet::<T>(0, true, message);
+        let packet = self.serialize_message(None, &message).await?;
 
-        Self::send_message_internal(
+        Self::broadcast_message_internal(
             Arc::clone(&self.active_connections_reference),
-            eventwork_packet,
+            &packet,
+            &self.network_event_send_channel,
         )
         .await
     }
+
+    async fn multicast(
+        &self,
+        connection_ids: Vec<usize>,
+        packet: SerializedPacket,
+    ) -> Result<(), EventWorkError> {
+        Self::multicast_message_internal(
+            Arc::clone(&self.active_connections_reference),
+            &connection_ids,
+            &packet,
+            &self.network_event_send_channel,
+        )
+        .await
+    }
+
+    async fn join_group(&self, group: &str, connection_id: usize) {
+        join_group_internal(&self.groups_reference, group, connection_id).await;
+    }
+
+    async fn leave_group(&self, group: &str, connection_id: usize) {
+        leave_group_internal(&self.groups_reference, group, connection_id).await;
+    }
+
+    async fn remove_group(&self, group: &str) {
+        self.groups_reference.lock().await.remove(group);
+    }
+
+    async fn send_to_group<T>(&self, group: &str, message: T) -> Result<(), EventWorkError>
+    where
+        T: NetworkMessage,
+    {
+        let packet = self.serialize_message(None, &message).await?;
+        let connection_ids = get_group_members(&self.groups_reference, group).await;
+
+        Self::multicast_message_internal(
+            Arc::clone(&self.active_connections_reference),
+            &connection_ids,
+            &packet,
+            &self.network_event_send_channel,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn take_all(bucket: &mut TokenBucket, now: Instant) -> u32 {
+        let mut taken = 0;
+        while bucket.try_take_at(now) {
+            taken += 1;
+        }
+        taken
+    }
+
+    #[test]
+    fn token_bucket_allows_a_full_burst_then_stops() {
+        let mut bucket = TokenBucket::new(RateLimit {
+            burst: 5,
+            per_second: 1.0,
+        });
+        let now = bucket.last_refill;
+
+        assert_eq!(take_all(&mut bucket, now), 5);
+        assert!(!bucket.try_take_at(now));
+    }
+
+    #[test]
+    fn token_bucket_refills_over_time() {
+        let mut bucket = TokenBucket::new(RateLimit {
+            burst: 5,
+            per_second: 2.0,
+        });
+        let start = bucket.last_refill;
+        take_all(&mut bucket, start);
+
+        assert_eq!(take_all(&mut bucket, start + Duration::from_millis(250)), 0);
+        assert_eq!(take_all(&mut bucket, start + Duration::from_millis(1000)), 2);
+    }
+
+    #[test]
+    fn token_bucket_refill_is_capped_at_burst() {
+        let mut bucket = TokenBucket::new(RateLimit {
+            burst: 3,
+            per_second: 10.0,
+        });
+        let start = bucket.last_refill;
+        take_all(&mut bucket, start);
+
+        assert_eq!(take_all(&mut bucket, start + Duration::from_secs(60)), 3);
+    }
+
+    #[test]
+    fn token_bucket_handles_fractional_rates() {
+        let mut bucket = TokenBucket::new(RateLimit {
+            burst: 1,
+            per_second: 0.25,
+        });
+        let start = bucket.last_refill;
+        assert_eq!(take_all(&mut bucket, start), 1);
+
+        // Partial tokens carry over between checks
+        assert!(!bucket.try_take_at(start + Duration::from_secs(2)));
+        assert!(bucket.try_take_at(start + Duration::from_secs(4)));
+        assert!(!bucket.try_take_at(start + Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn strike_thresholds_are_counted_separately() {
+        let mut strikes = StrikeCounter::default();
+        let malformed = EventWorkError::Serialization("bad".to_string());
+        let unknown = EventWorkError::UnknownMessageKind("Unknown".to_string());
+        let bid_limit = EventWorkError::RateLimited(Some("Bid".to_string()));
+        let chat_limit = EventWorkError::RateLimited(Some("Chat".to_string()));
+
+        assert!(!strikes.strike(&bid_limit, PacketPolicy::Strikes(3)));
+        assert!(!strikes.strike(&bid_limit, PacketPolicy::Strikes(3)));
+
+        // Other kinds of bad packets don't add to the bid limit's strikes
+        assert!(!strikes.strike(&malformed, PacketPolicy::Strikes(3)));
+        assert!(!strikes.strike(&unknown, PacketPolicy::Strikes(3)));
+        assert!(!strikes.strike(&chat_limit, PacketPolicy::Strikes(3)));
+        assert!(!strikes.strike(&unknown, PacketPolicy::Strikes(3)));
+
+        assert!(strikes.strike(&bid_limit, PacketPolicy::Strikes(3)));
+        assert!(strikes.strike(&unknown, PacketPolicy::Strikes(3)));
+    }
+
+    #[test]
+    fn message_limit_strikes_clear_once_the_kind_gets_through() {
+        let mut strikes = StrikeCounter::default();
+        let bid_limit = EventWorkError::RateLimited(Some("Bid".to_string()));
+        let chat_limit = EventWorkError::RateLimited(Some("Chat".to_string()));
+
+        assert!(!strikes.strike(&bid_limit, PacketPolicy::Strikes(2)));
+        assert!(!strikes.strike(&chat_limit, PacketPolicy::Strikes(2)));
+        strikes.clear_message_limit("Bid");
+
+        assert!(!strikes.strike(&bid_limit, PacketPolicy::Strikes(2)));
+        assert!(strikes.strike(&chat_limit, PacketPolicy::Strikes(2)));
+    }
+
+    #[test]
+    fn strike_policies_ignore_and_disconnect_skip_the_count() {
+        let mut strikes = StrikeCounter::default();
+        let malformed = EventWorkError::Serialization("bad".to_string());
+
+        assert!(!strikes.strike(&malformed, PacketPolicy::Ignore));
+        assert!(!strikes.strike(&malformed, PacketPolicy::Ignore));
+        assert!(strikes.strike(&malformed, PacketPolicy::Disconnect));
+        assert!(!strikes.strike(&malformed, PacketPolicy::Strikes(2)));
+    }
 }
@@ -4,6 +4,7 @@ use bevy_egui::EguiPlugin;
 mod scenes;
 use scenes::add_scenes;
 mod resources;
+mod rpc;
 use server_responses::*;
 
 const SCREEN_SCALING_SIZE: f32 = 100.0;
@@ -28,11 +29,13 @@ fn main() {
     .insert_resource(resources::PlayerSettings {
         username: String::new(),
     })
-    .insert_resource(resources::CurrentPlayerData { player_id: 0 })
+    .insert_resource(resources::CurrentPlayerData {
+        player_id: PlayerId::default(),
+    })
     .insert_resource(resources::FrontEndPromptList::default())
     .insert_resource(RoundEndInfo::default())
     .insert_resource(GameEndInfo::default())
-    .insert_resource(RoundTimer(Timer::from_seconds(5.0, TimerMode::Once)))
+    .insert_resource(resources::ServerClock::default())
     .add_systems(Startup, setup)
     .add_systems(Update, update_camera_scaling)
     .add_systems(Update, tick_timers)
@@ -73,11 +76,8 @@ fn update_camera_scaling(
 
 fn tick_timers(
     time: Res<Time>,
-    mut round_timer: ResMut<RoundTimer>,
     mut notification_timers: Query<&mut GamePlayerNotification>,
 ) {
-    round_timer.0.tick(time.delta());
-
     for mut game_notification in notification_timers.iter_mut() {
         game_notification.timer.tick(time.delta());
     }
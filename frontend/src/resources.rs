@@ -1,5 +1,7 @@
+use std::collections::VecDeque;
+
 use bevy::prelude::*;
-use server_responses::PromptInfoDataRequest;
+use server_responses::{PlayerId, PromptInfoDataRequest, RoomJoinRejection};
 
 #[derive(Resource)]
 pub struct PlayerSettings {
@@ -8,10 +10,105 @@ pub struct PlayerSettings {
 
 #[derive(Resource)]
 pub struct CurrentPlayerData {
-    pub player_id: u32,
+    pub player_id: PlayerId,
+}
+
+// Lets us ask for our seat back if the connection to the server drops
+#[derive(Resource, Default)]
+pub struct PlayerSession {
+    pub session_token: String,
+}
+
+#[derive(Resource)]
+pub struct ReconnectTimer(pub Timer);
+
+// Why the server turned down our last room join request, shown on the intro screen
+#[derive(Resource, Default)]
+pub struct RoomJoinStatus {
+    pub rejection: Option<RoomJoinRejection>,
+}
+
+// Set when the server turned down our Hello because it's running a different version
+#[derive(Resource, Default)]
+pub struct ServerHandshake {
+    pub rejected_server_version: Option<u8>,
+}
+
+// How many recent clock syncs the offset is picked from
+const CLOCK_SAMPLE_WINDOW: usize = 5;
+
+// Our best guess at the server's clock, worked out from clock sync round trips.
+// Local times are milliseconds since the app started.
+#[derive(Resource, Default)]
+pub struct ServerClock {
+    pub offset_ms: Option<i64>,
+    pub round_trip_ms: i64,
+    // Offset and round trip of the latest syncs, older ones age out so clock drift gets corrected
+    samples: VecDeque<(i64, i64)>,
+}
+
+impl ServerClock {
+    pub fn add_sample(&mut self, client_send_ms: i64, server_time_ms: i64, client_receive_ms: i64) {
+        let round_trip_ms = (client_receive_ms - client_send_ms).max(0);
+        let offset_ms = server_time_ms + round_trip_ms / 2 - client_receive_ms;
+
+        self.samples.push_back((offset_ms, round_trip_ms));
+        if self.samples.len() > CLOCK_SAMPLE_WINDOW {
+            self.samples.pop_front();
+        }
+
+        // Quicker round trips give a tighter estimate, so use the best recent one
+        if let Some((offset_ms, round_trip_ms)) = self
+            .samples
+            .iter()
+            .min_by_key(|(_offset_ms, round_trip_ms)| *round_trip_ms)
+        {
+            self.offset_ms = Some(*offset_ms);
+            self.round_trip_ms = *round_trip_ms;
+        }
+    }
+
+    // Forget every sync, as if we had never heard from the server
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn secs_until(&self, server_deadline_ms: i64, local_time_ms: i64) -> f32 {
+        match self.offset_ms {
+            Some(offset_ms) => {
+                ((server_deadline_ms - (local_time_ms + offset_ms)) as f32 / 1000.0).max(0.0)
+            }
+            None => 0.0,
+        }
+    }
 }
 
 #[derive(Resource, Default)]
 pub struct FrontEndPromptList {
     pub prompt_data_list: Vec<PromptInfoDataRequest>,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_clock_ages_out_old_samples() {
+        let mut server_clock = ServerClock::default();
+
+        // A quick sync against the old clock
+        server_clock.add_sample(0, 1_000, 10);
+        assert_eq!(server_clock.offset_ms, Some(995));
+
+        // Slower syncs after the server clock drifted still win once the quick one ages out
+        for sync_number in 1..=CLOCK_SAMPLE_WINDOW as i64 {
+            let client_send_ms = sync_number * 30_000;
+            server_clock.add_sample(client_send_ms, client_send_ms + 2_050, client_send_ms + 100);
+        }
+        assert_eq!(server_clock.offset_ms, Some(2_000));
+        assert_eq!(server_clock.round_trip_ms, 100);
+
+        server_clock.reset();
+        assert_eq!(server_clock.offset_ms, None);
+    }
+}
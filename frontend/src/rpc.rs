@@ -0,0 +1,107 @@
+use std::{collections::HashMap, marker::PhantomData, time::Duration};
+
+use bevy::prelude::*;
+use bevy_eventwork::{error::NetworkError, AppNetworkMessage, ConnectionId, Network, NetworkData};
+use bevy_eventwork_mod_websockets::WebSocketProvider;
+use server_responses::{RpcCall, RpcMethod, RpcReply};
+
+#[derive(Debug, Clone)]
+pub enum RpcError<E> {
+    // No reply came back in time, a reply that turns up later is ignored
+    Timeout,
+    // The server's handler returned an error
+    Failed(E),
+}
+
+// Sent once for every call, with the reply or the reason there isn't one
+#[derive(Event, Debug)]
+pub struct RpcResult<T: RpcMethod> {
+    pub request_id: u64,
+    pub result: Result<T::Response, RpcError<T::Error>>,
+}
+
+// Calls to one method that are still waiting for a reply, with the time they give up at
+#[derive(Resource)]
+pub struct RpcClient<T: RpcMethod> {
+    next_request_id: u64,
+    pending: HashMap<u64, Duration>,
+    _method: PhantomData<T>,
+}
+
+impl<T: RpcMethod> Default for RpcClient<T> {
+    fn default() -> Self {
+        Self {
+            next_request_id: 0,
+            pending: HashMap::new(),
+            _method: PhantomData,
+        }
+    }
+}
+
+impl<T: RpcMethod> RpcClient<T> {
+    // Returns the request id, the outcome arrives later as an RpcResult<T> event with the same id
+    pub fn call(
+        &mut self,
+        request: T,
+        timeout: Duration,
+        connection_id: ConnectionId,
+        time: &Time<Real>,
+        net: &Network<WebSocketProvider>,
+    ) -> Result<u64, NetworkError> {
+        self.next_request_id += 1;
+        let request_id = self.next_request_id;
+
+        net.send_message(connection_id, RpcCall { request_id, request })?;
+        self.pending.insert(request_id, time.elapsed() + timeout);
+
+        Ok(request_id)
+    }
+}
+
+fn receive_rpc_replies<T: RpcMethod>(
+    mut new_messages: EventReader<NetworkData<RpcReply<T>>>,
+    mut rpc_client: ResMut<RpcClient<T>>,
+    mut rpc_results: EventWriter<RpcResult<T>>,
+) {
+    for new_message in new_messages.read() {
+        if rpc_client.pending.remove(&new_message.request_id).is_none() {
+            warn!(
+                "Ignoring {} reply for unknown or timed out request {}",
+                T::NAME,
+                new_message.request_id
+            );
+            continue;
+        }
+
+        rpc_results.send(RpcResult {
+            request_id: new_message.request_id,
+            result: new_message.result.clone().map_err(RpcError::Failed),
+        });
+    }
+}
+
+fn expire_rpc_calls<T: RpcMethod>(
+    time: Res<Time<Real>>,
+    mut rpc_client: ResMut<RpcClient<T>>,
+    mut rpc_results: EventWriter<RpcResult<T>>,
+) {
+    let now = time.elapsed();
+    rpc_client.pending.retain(|request_id, deadline| {
+        if *deadline > now {
+            return true;
+        }
+
+        rpc_results.send(RpcResult {
+            request_id: *request_id,
+            result: Err(RpcError::Timeout),
+        });
+        false
+    });
+}
+
+pub fn add_rpc_method<T: RpcMethod>(app: &mut App) {
+    app.insert_resource(RpcClient::<T>::default())
+        .add_event::<RpcResult<T>>()
+        .listen_for_message::<RpcReply<T>, WebSocketProvider>()
+        .add_systems(Update, (receive_rpc_replies::<T>, expire_rpc_calls::<T>));
+}
@@ -1,4 +1,5 @@
 use bevy::{
+    ecs::system::SystemParam,
     prelude::*,
     render::{
         render_asset::RenderAssetUsages,
@@ -18,10 +19,14 @@ use backend_server_connections::*;
 
 use server_responses::*;
 
-use bevy_async_task::AsyncTaskRunner;
+use bevy_async_task::{AsyncTaskPool, AsyncTaskRunner};
 
 use ::image::ImageReader;
-use std::{collections::HashMap, io::Cursor, task::Poll};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Cursor,
+    task::Poll,
+};
 
 // === Assets ===
 #[derive(Resource, Debug, Default)]
@@ -29,6 +34,13 @@ pub struct Images {
     current_bid_image: Option<Handle<Image>>,
 }
 
+// Art shown on the end score screen, keyed by image url
+#[derive(Resource, Debug, Default)]
+pub struct GalleryImages {
+    images: HashMap<String, Handle<Image>>,
+    requested: HashSet<String>,
+}
+
 #[derive(Component)]
 pub struct BidImage;
 
@@ -43,6 +55,16 @@ pub struct BidImage;
 
 // === Helper functions ===
 
+// Seconds left in the current phase going by the server's deadline
+fn phase_secs_left(room_state: &RoomState, server_clock: &ServerClock, time: &Time<Real>) -> f32 {
+    match room_state.phase_deadline_ms {
+        Some(deadline_ms) => {
+            server_clock.secs_until(deadline_ms, time.elapsed().as_millis() as i64)
+        }
+        None => 0.0,
+    }
+}
+
 fn timer_value_to_alpha_function(
     remaining_time_value: f32,
     fade_in_value: f32,
@@ -64,6 +86,57 @@ fn timer_value_to_alpha_function(
     return return_value as u8;
 }
 
+// Downloads an image from a url and turns it into a Bevy texture
+async fn download_image(url: String) -> Option<Image> {
+    info!("Started image loading for: {}", url.escape_debug());
+
+    let client = reqwest::Client::new();
+    let response = client.get(resolve_image_url(&url)).send().await;
+
+    match response {
+        Ok(resp) => {
+            if resp.status().is_success() {
+                let bytes = resp.bytes().await.ok()?;
+                // Decode the image
+                let reader = ImageReader::new(Cursor::new(bytes))
+                    .with_guessed_format()
+                    .ok()?;
+                let image = reader.decode().ok()?; // Decode the image from the reader
+                let rgba_image = image.to_rgba8();
+                let (width, height) = rgba_image.dimensions();
+                info!("Image dimensions: {}x{}", width, height);
+
+                // Create a Bevy texture
+                let texture = Image::new_fill(
+                    Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                    TextureDimension::D2,
+                    &rgba_image,
+                    TextureFormat::Rgba8UnormSrgb,
+                    RenderAssetUsages::RENDER_WORLD,
+                );
+
+                info!("Finished image loading");
+
+                Some(texture)
+            } else {
+                info!("HTTP error: {}", resp.status());
+                if let Ok(text) = resp.text().await {
+                    info!("Response body: {}", text);
+                }
+                None
+            }
+        }
+        Err(e) => {
+            info!("Failed to fetch url at all: {:?}", e);
+            None
+        }
+    }
+}
+
 // === Intro scenes ===
 
 pub fn draw_intro_ui(
@@ -71,6 +144,7 @@ pub fn draw_intro_ui(
     mut input_text: Local<String>,
     mut room_code_text: Local<String>,
     mut player_settings: ResMut<PlayerSettings>,
+    room_join_status: Res<RoomJoinStatus>,
     net: Res<Network<WebSocketProvider>>,
 ) {
     if player_settings.username != "" {
@@ -80,6 +154,14 @@ pub fn draw_intro_ui(
             .show(contexts.ctx_mut(), |ui| {
                 ui.vertical(|ui| {
                     ui.label("Select a room");
+
+                    if let Some(rejection) = &room_join_status.rejection {
+                        ui.label(
+                            RichText::new(rejection.to_string())
+                                .color(egui::Color32::from_rgb(255, 100, 100)),
+                        );
+                    }
+
                     ui.vertical(|ui| {
                         let random_room = ui.button("Join random room");
                         ui.add_space(10.0);
@@ -89,10 +171,20 @@ pub fn draw_intro_ui(
                             send_random_room_request(player_settings.username.as_str(), &net);
                         }
 
+                        let create_private_room = ui.button("Create private room");
+                        if create_private_room.clicked() {
+                            info!("Creating private room");
+
+                            send_create_private_room_request(
+                                player_settings.username.as_str(),
+                                &net,
+                            );
+                        }
+
                         ui.add_space(10.0);
 
                         ui.vertical(|ui| {
-                            ui.label("Enter custom room code");
+                            ui.label("Enter a friend's room code");
                             ui.text_edit_singleline(&mut *room_code_text);
                             let private_room = ui.add_enabled(
                                 room_code_text.len() > 0,
@@ -139,42 +231,157 @@ pub fn add_intro_scenes(app: &mut App) {
 pub fn draw_waiting_room_ui(
     mut contexts: EguiContexts,
     mut query: Query<&mut RoomState>,
-    player_settings: ResMut<PlayerSettings>,
+    mut edited_room_settings: Local<Option<RoomSettings>>,
+    current_player_data: Res<CurrentPlayerData>,
     net: Res<Network<WebSocketProvider>>,
 ) {
     // If an entity with room state exists, update it
     let room_state = query.get_single_mut().unwrap();
 
+    let is_host = match room_state.players.get(0) {
+        Some(host) => host.id == current_player_data.player_id,
+        None => false,
+    };
+
     // For each player in the room, display their username and money
     egui::Window::new("waiting_room_area".to_string())
         .anchor(Align2::CENTER_TOP, (0., 200.))
         .show(contexts.ctx_mut(), |ui| {
             ui.vertical(|ui| {
                 ui.label("Waiting room");
+                if !room_state.is_public {
+                    ui.label(
+                        RichText::new(format!("Room code: {}", room_state.room_code)).strong(),
+                    );
+                    ui.label("Share this code with friends so they can join");
+                }
+                ui.add_space(5.0);
+
                 for player in room_state.players.iter() {
                     ui.horizontal(|ui| {
                         ui.label(player.username.clone());
+                        if player.connection_status == ConnectionStatus::Reconnecting {
+                            ui.label(RichText::new("(reconnecting)").weak());
+                        }
                     });
                 }
 
-                // Check if the current player is the host (player in position 0)
-                if let Some(host) = room_state.players.get(0) {
-                    // TODO: Do an ID based check instead of username check
-                    if host.username == player_settings.username {
-                        // Replace with actual current player username check
-                        let button = ui.add_enabled(
-                            room_state.players.len() >= MIN_PLAYERS,
-                            egui::Button::new("Start Game"),
-                        );
-                        if button.clicked() {
-                            send_start_game_request(room_state.room_id, net);
+                ui.add_space(10.0);
+
+                if is_host {
+                    // Drop edits left over from a room we were in before
+                    if edited_room_settings
+                        .as_ref()
+                        .is_some_and(|room_settings| room_settings.room_id != room_state.room_id)
+                    {
+                        *edited_room_settings = None;
+                    }
+
+                    // Let the host edit a copy of the settings and send it when they're done
+                    let room_settings = edited_room_settings
+                        .get_or_insert_with(|| room_state.settings.additional_clone());
+
+                    ui.label("Room settings");
+                    draw_room_settings_editor(ui, room_settings);
+
+                    let mut reset_clicked = false;
+                    ui.horizontal(|ui| {
+                        let changed = *room_settings != room_state.settings;
+                        if ui
+                            .add_enabled(changed, egui::Button::new("Apply settings"))
+                            .clicked()
+                        {
+                            send_room_settings_request(room_settings, &net);
                         }
+                        reset_clicked = ui.add_enabled(changed, egui::Button::new("Reset")).clicked();
+                    });
+
+                    if let Err(e) = room_settings.validate() {
+                        ui.label(
+                            egui::RichText::new(e).color(egui::Color32::from_rgb(255, 100, 100)),
+                        );
+                    }
+
+                    if reset_clicked {
+                        *edited_room_settings = None;
+                    }
+
+                    ui.add_space(10.0);
+
+                    let button = ui.add_enabled(
+                        room_state.players.len() >= room_state.config.min_players,
+                        egui::Button::new("Start Game"),
+                    );
+                    if button.clicked() {
+                        send_start_game_request(room_state.room_id, net);
                     }
+                } else {
+                    *edited_room_settings = None;
+                    draw_room_settings_summary(ui, &room_state.settings);
                 }
             });
         });
 }
 
+fn draw_room_settings_editor(ui: &mut egui::Ui, room_settings: &mut RoomSettings) {
+    ui.horizontal(|ui| {
+        let mut choose_prompts = room_settings.prompts_per_player.is_some();
+        ui.checkbox(&mut choose_prompts, "Prompts per player");
+        if choose_prompts {
+            let prompts_per_player = room_settings.prompts_per_player.get_or_insert(2);
+            ui.add(egui::DragValue::new(prompts_per_player).range(1..=5));
+        } else {
+            room_settings.prompts_per_player = None;
+            ui.label("Automatic");
+        }
+    });
+    ui.horizontal(|ui| {
+        ui.label("Bidding time (seconds)");
+        ui.add(egui::DragValue::new(&mut room_settings.bidding_round_time).range(10..=300));
+    });
+    ui.horizontal(|ui| {
+        ui.label("Starting money");
+        ui.add(
+            egui::DragValue::new(&mut room_settings.starting_money)
+                .range(100..=100_000)
+                .speed(50),
+        );
+    });
+    ui.horizontal(|ui| {
+        ui.label("Force bids per player");
+        ui.add(egui::DragValue::new(&mut room_settings.force_bids_per_player).range(0..=10));
+    });
+    ui.horizontal(|ui| {
+        ui.label("Art value range");
+        ui.add(
+            egui::DragValue::new(&mut room_settings.min_art_value)
+                .range(1..=100_000)
+                .speed(50),
+        );
+        ui.label("to");
+        ui.add(
+            egui::DragValue::new(&mut room_settings.max_art_value)
+                .range(1..=100_000)
+                .speed(50),
+        );
+    });
+}
+
+fn draw_room_settings_summary(ui: &mut egui::Ui, room_settings: &RoomSettings) {
+    ui.label("Room settings");
+    match room_settings.prompts_per_player {
+        Some(prompts_per_player) => ui.label(format!("Prompts per player: {}", prompts_per_player)),
+        None => ui.label("Prompts per player: Automatic"),
+    };
+    ui.label(format!("Bidding time: {} seconds", room_settings.bidding_round_time));
+    ui.label(format!("Starting money: {}", room_settings.starting_money));
+    ui.label(format!("Force bids per player: {}", room_settings.force_bids_per_player));
+    ui.label(format!(
+        "Art value range: {} to {}",
+        room_settings.min_art_value, room_settings.max_art_value
+    ));
+}
+
 pub fn add_waiting_room_scenes(app: &mut App) {
     app.add_systems(
         Update,
@@ -280,7 +487,8 @@ pub fn add_image_creation_scenes(app: &mut App) {
 
 pub fn draw_bidding_round_ui(
     mut contexts: EguiContexts,
-    round_timer: ResMut<RoundTimer>,
+    server_clock: Res<ServerClock>,
+    real_time: Res<Time<Real>>,
     mut query: Query<&mut RoomState>,
     current_player_data: Res<CurrentPlayerData>,
     net: Res<Network<WebSocketProvider>>,
@@ -306,57 +514,7 @@ pub fn draw_bidding_round_ui(
         if task_executor.is_idle() {
             let url = room_state.current_art_bid.prompt_info.image_url.clone();
             // Spawn an async task to download the image
-            task_executor.start(async move {
-                info!("Started image loading for: {}", url.escape_debug());
-
-                let client = reqwest::Client::new();
-                let response = client.get(&url).send().await;
-
-                match response {
-                    Ok(resp) => {
-                        if resp.status().is_success() {
-                            let bytes = resp.bytes().await.unwrap();
-                            // Decode the image
-                            let reader = ImageReader::new(Cursor::new(bytes))
-                                .with_guessed_format()
-                                .unwrap(); // Correct use of Result
-                            let image = reader.decode().unwrap(); // Decode the image from the reader
-                            let rgba_image = image.to_rgba8();
-                            let (width, height) = rgba_image.dimensions();
-                            info!("Image dimensions: {}x{}", width, height);
-
-                            // Create a Bevy texture
-                            let texture = Image::new_fill(
-                                Extent3d {
-                                    width,
-                                    height,
-                                    depth_or_array_layers: 1,
-                                },
-                                TextureDimension::D2,
-                                &rgba_image,
-                                TextureFormat::Rgba8UnormSrgb,
-                                RenderAssetUsages::RENDER_WORLD,
-                            );
-
-                            info!("Finished image loading");
-
-                            Some(texture)
-                        } else {
-                            info!("HTTP error: {}", resp.status());
-                            if let Ok(text) = resp.text().await {
-                                info!("Response body: {}", text);
-                                panic!();
-                            } else {
-                                panic!();
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        info!("Failed to fetch url at all: {:?}", e);
-                        panic!();
-                    }
-                }
-            });
+            task_executor.start(download_image(url));
         }
     }
 
@@ -411,7 +569,10 @@ pub fn draw_bidding_round_ui(
                     // Show timer information at top
                     ui.vertical(|ui| {
                         ui.label("Time left: ");
-                        ui.label(format!("{:.2}", round_timer.0.remaining_secs()));
+                        ui.label(format!(
+                            "{:.2}",
+                            phase_secs_left(&room_state, &server_clock, &real_time)
+                        ));
                     });
 
                     ui.vertical(|ui| {
@@ -440,6 +601,13 @@ pub fn draw_bidding_round_ui(
                         ui.label(format!("{}", current_player.money));
                     });
 
+                    if current_player.debt > 0 {
+                        ui.vertical(|ui| {
+                            ui.label("Debt:");
+                            ui.label(format!("{}", current_player.debt));
+                        });
+                    }
+
                     ui.vertical(|ui| {
                         ui.add_space(1.0);
                     });
@@ -449,7 +617,7 @@ pub fn draw_bidding_round_ui(
                 if *game_state.get() == GameState::BiddingRound {
                     // Prepare hash map for player notifications
                     let mut player_notifications_map =
-                        HashMap::<u32, Vec<&GamePlayerNotification>>::new();
+                        HashMap::<PlayerId, Vec<&GamePlayerNotification>>::new();
                     for notification in notifications_query.iter() {
                         if let Some(notification_list) =
                             player_notifications_map.get_mut(&notification.target_player_id)
@@ -486,6 +654,10 @@ pub fn draw_bidding_round_ui(
                                     );
                                 }
 
+                                if player.connection_status == ConnectionStatus::Reconnecting {
+                                    ui.label(RichText::new("(reconnecting)").weak());
+                                }
+
                                 ui.label(format!("Force bids: {}", player.force_bids_left));
 
                                 if player.id == current_player_data.player_id {
@@ -567,14 +739,6 @@ pub fn draw_bidding_round_ui(
         });
 }
 
-pub fn on_enter_bidding_round(mut round_timer: ResMut<RoundTimer>) {
-    // Create a new round timer
-    *round_timer = RoundTimer(Timer::from_seconds(
-        BIDDING_ROUND_TIME as f32 - 1.0,
-        TimerMode::Once,
-    ));
-}
-
 pub fn on_exit_bidding_round_end(
     mut commands: Commands,
     query: Query<Entity, With<BidImage>>,
@@ -609,26 +773,174 @@ pub fn add_bidding_round_scenes(app: &mut App) {
         Update,
         draw_bidding_round_ui.run_if(in_state(InBiddingRound)),
     );
-    app.add_systems(OnEnter(GameState::BiddingRound), on_enter_bidding_round);
     app.add_systems(
         OnExit(GameState::BiddingRoundEnd),
         on_exit_bidding_round_end,
     );
 }
 
+// === Loan round scenes ===
+
+pub fn draw_loan_round_ui(
+    mut contexts: EguiContexts,
+    query: Query<&RoomState>,
+    current_player_data: Res<CurrentPlayerData>,
+    server_clock: Res<ServerClock>,
+    real_time: Res<Time<Real>>,
+    mut loan_amount: Local<u32>,
+    net: Res<Network<WebSocketProvider>>,
+) {
+    let room_state = match query.get_single() {
+        Ok(room_state) => room_state,
+        Err(_) => return,
+    };
+
+    let current_player = match room_state
+        .players
+        .iter()
+        .find(|player| player.id == current_player_data.player_id)
+    {
+        Some(player) => player,
+        None => return,
+    };
+
+    let interest_rate = room_state.config.loan_interest_rate;
+    let available_debt = room_state.config.max_debt.saturating_sub(current_player.debt);
+    let max_borrow = (available_debt as f32 / (1.0 + interest_rate)).floor() as u32;
+
+    egui::Window::new("loan_round_area".to_string())
+        .anchor(Align2::CENTER_TOP, (0., 0.))
+        .show(contexts.ctx_mut(), |ui| {
+            ui.vertical(|ui| {
+                ui.label("Time left: ");
+                ui.label(format!(
+                    "{:.2}",
+                    phase_secs_left(room_state, &server_clock, &real_time)
+                ));
+            });
+
+            ui.heading("Loan office");
+            ui.label(format!(
+                "Borrow money for the next round, {:.0}% interest is added to what you owe.",
+                interest_rate * 100.0
+            ));
+            ui.label("Whatever you still owe is taken off your money at the end of the game.");
+
+            ui.add_space(5.0);
+            ui.label(format!("Money: {}", current_player.money));
+            ui.label(format!("Debt: {}", current_player.debt));
+
+            ui.add_space(5.0);
+            ui.horizontal(|ui| {
+                ui.label("Amount");
+                ui.add(
+                    egui::DragValue::new(&mut *loan_amount)
+                        .range(0..=room_state.config.max_debt)
+                        .speed(50),
+                );
+            });
+
+            ui.horizontal(|ui| {
+                let borrow_button = ui.add_enabled(
+                    *loan_amount > 0 && *loan_amount <= max_borrow,
+                    egui::Button::new("Borrow"),
+                );
+                if borrow_button.clicked() {
+                    send_loan_request(room_state.room_id, LoanAction::Borrow, *loan_amount, &net);
+                }
+
+                let repay_button = ui.add_enabled(
+                    *loan_amount > 0
+                        && current_player.debt > 0
+                        && current_player.money >= loan_amount.min(current_player.debt) as i32,
+                    egui::Button::new("Repay"),
+                );
+                if repay_button.clicked() {
+                    send_loan_request(room_state.room_id, LoanAction::Repay, *loan_amount, &net);
+                }
+            });
+
+            ui.label(format!("You can borrow up to {}", max_borrow));
+
+            ui.add_space(10.0);
+            for player in room_state.players.iter() {
+                ui.label(format!(
+                    "{}: {} money, {} debt",
+                    player.username, player.money, player.debt
+                ));
+            }
+        });
+}
+
+pub fn add_loan_round_scenes(app: &mut App) {
+    app.add_systems(
+        Update,
+        draw_loan_round_ui.run_if(in_state(GameState::LoanRound)),
+    );
+}
+
 // === End score screen scenes ===
 
+// Everything the gallery needs to download the art and keep the images around
+#[derive(SystemParam)]
+pub struct GalleryDownloads<'w, 's> {
+    gallery_images: ResMut<'w, GalleryImages>,
+    task_pool: AsyncTaskPool<'s, (String, Option<Image>)>,
+    asset_server: ResMut<'w, AssetServer>,
+}
+
 pub fn draw_end_score_screen_ui(
     mut contexts: EguiContexts,
     game_end_info: Res<GameEndInfo>,
-    round_timer: Res<RoundTimer>,
+    server_clock: Res<ServerClock>,
+    real_time: Res<Time<Real>>,
+    query: Query<&RoomState>,
+    mut gallery_downloads: GalleryDownloads,
 ) {
+    let GalleryDownloads {
+        gallery_images,
+        task_pool,
+        asset_server,
+    } = &mut gallery_downloads;
+
+    let secs_left = match query.get_single() {
+        Ok(room_state) => phase_secs_left(room_state, &server_clock, &real_time),
+        Err(_) => 0.0,
+    };
+
+    // Start downloading any art we haven't asked for yet
+    for player in game_end_info.players.iter() {
+        for art_piece in player.art_collection.iter() {
+            if gallery_images.requested.insert(art_piece.image_url.clone()) {
+                let url = art_piece.image_url.clone();
+                task_pool.spawn(async move {
+                    let image = download_image(url.clone()).await;
+                    (url, image)
+                });
+            }
+        }
+    }
+
+    for status in task_pool.iter_poll() {
+        if let Poll::Ready(Ok((url, Some(image)))) = status {
+            let image_handle = asset_server.add(image);
+            gallery_images.images.insert(url, image_handle);
+        }
+    }
+
+    // Register the downloaded images with egui before drawing
+    let texture_ids: HashMap<String, egui::TextureId> = gallery_images
+        .images
+        .iter()
+        .map(|(url, image_handle)| (url.clone(), contexts.add_image(image_handle.clone_weak())))
+        .collect();
+
     egui::Window::new("end_score_screen_area".to_string())
         .anchor(Align2::CENTER_TOP, (0., 0.))
         .show(contexts.ctx_mut(), |ui| {
             ui.vertical(|ui| {
                 ui.label("Time left: ");
-                ui.label(format!("{:.2}", round_timer.0.remaining_secs()));
+                ui.label(format!("{:.2}", secs_left));
             });
             ui.label("End score screen");
 
@@ -640,17 +952,69 @@ pub fn draw_end_score_screen_ui(
                         player.username,
                         player.money
                     ));
+                    if player.debt > 0 {
+                        ui.label(format!("(after paying back {} debt)", player.debt));
+                    }
                 });
             }
+
+            ui.add_space(10.0);
+            ui.heading("Gallery");
+
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for player in game_end_info.players.iter() {
+                    ui.label(RichText::new(format!("{}'s collection", player.username)).strong());
+
+                    if player.art_collection.is_empty() {
+                        ui.label("No art bought");
+                    }
+
+                    ui.horizontal_wrapped(|ui| {
+                        for art_piece in player.art_collection.iter() {
+                            ui.vertical(|ui| {
+                                match texture_ids.get(&art_piece.image_url) {
+                                    Some(texture_id) => {
+                                        ui.image((*texture_id, egui::vec2(96.0, 96.0)));
+                                    }
+                                    None => {
+                                        ui.label("Loading...");
+                                    }
+                                }
+
+                                ui.label(art_piece.prompt_answer.clone());
+                                ui.label(format!(
+                                    "Paid {} for {}",
+                                    art_piece.price_paid, art_piece.art_value
+                                ));
+
+                                let profit_color = if art_piece.profit >= 0 {
+                                    egui::Color32::from_rgb(100, 200, 100)
+                                } else {
+                                    egui::Color32::from_rgb(255, 100, 100)
+                                };
+                                ui.label(
+                                    RichText::new(format!("Profit: {}", art_piece.profit))
+                                        .color(profit_color),
+                                );
+                            });
+                        }
+                    });
+
+                    ui.add_space(5.0);
+                }
+            });
         });
 }
 
-pub fn on_enter_end_score_screen(mut round_timer: ResMut<RoundTimer>) {
-    // Create a new round timer
-    *round_timer = RoundTimer(Timer::from_seconds(
-        END_SCORE_SCREEN_TIME as f32 - 1.0,
-        TimerMode::Once,
-    ));
+pub fn on_exit_end_score_screen(
+    mut contexts: EguiContexts,
+    mut gallery_images: ResMut<GalleryImages>,
+) {
+    for image_handle in gallery_images.images.values() {
+        contexts.remove_image(image_handle);
+    }
+
+    *gallery_images = GalleryImages::default();
 }
 
 pub fn add_end_score_screen_scenes(app: &mut App) {
@@ -658,10 +1022,7 @@ pub fn add_end_score_screen_scenes(app: &mut App) {
         Update,
         draw_end_score_screen_ui.run_if(in_state(GameState::EndScoreScreen)),
     );
-    app.add_systems(
-        OnEnter(GameState::EndScoreScreen),
-        on_enter_end_score_screen,
-    );
+    app.add_systems(OnExit(GameState::EndScoreScreen), on_exit_end_score_screen);
 }
 
 // Default scenes
@@ -682,17 +1043,51 @@ fn draw_version_number (
     }
 }
 
+// Covers the whole screen when the server is running a different version than us
+fn draw_refresh_screen(mut contexts: EguiContexts, server_handshake: Res<ServerHandshake>) {
+    let server_version = match server_handshake.rejected_server_version {
+        Some(server_version) => server_version,
+        None => return,
+    };
+
+    let ctx = contexts.ctx_mut();
+    let screen_rect = ctx.screen_rect();
+
+    egui::Area::new("refresh_screen".into())
+        .order(egui::Order::Foreground)
+        .fixed_pos(screen_rect.min)
+        .show(ctx, |ui| {
+            egui::Frame::none()
+                .fill(egui::Color32::from_black_alpha(230))
+                .show(ui, |ui| {
+                    ui.set_min_size(screen_rect.size());
+                    ui.vertical_centered(|ui| {
+                        ui.add_space(screen_rect.height() / 3.0);
+                        ui.label(RichText::new("A new version of Artbabo is out!").size(32.0));
+                        ui.label(format!(
+                            "The server is running version {} and you have version {}.",
+                            server_version, GAME_VERSION
+                        ));
+                        ui.label(RichText::new("Please refresh the page to keep playing.").size(20.0));
+                    });
+                });
+        });
+}
+
 // === Main add logic ===
 pub fn add_scenes(app: &mut App) {
     app.init_state::<GameState>();
     app.add_computed_state::<InBiddingRound>();
     app.insert_resource(Images::default());
+    app.insert_resource(GalleryImages::default());
     app.add_systems(Update, draw_version_number);
+    app.add_systems(Update, draw_refresh_screen);
     add_intro_scenes(app);
     add_waiting_room_scenes(app);
     add_prompt_generation_scenes(app);
     add_image_creation_scenes(app);
     add_backend_server_connections(app);
     add_bidding_round_scenes(app);
+    add_loan_round_scenes(app);
     add_end_score_screen_scenes(app);
 }
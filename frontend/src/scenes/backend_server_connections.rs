@@ -3,9 +3,13 @@ use std::time::Duration;
 use bevy::{
     prelude::*,
     tasks::{TaskPool, TaskPoolBuilder},
+    time::common_conditions::on_timer,
 };
 
-use crate::resources::CurrentPlayerData;
+use crate::resources::{
+    CurrentPlayerData, PlayerSession, ReconnectTimer, RoomJoinStatus, ServerClock, ServerHandshake,
+};
+use crate::rpc::{add_rpc_method, RpcClient, RpcResult};
 
 use bevy_eventwork::{
     AppNetworkMessage, ConnectionId, EventworkRuntime, Network, NetworkData, NetworkEvent,
@@ -16,13 +20,29 @@ use server_responses::*;
 use super::FrontEndPromptList;
 
 const SERVER_CONNECTION_ID: ConnectionId = ConnectionId { id: 0 };
+const RECONNECT_INTERVAL_SECS: f32 = 3.0;
+const CLOCK_SYNC_INTERVAL_SECS: u64 = 30;
+const CLOCK_SYNC_TIMEOUT_SECS: u64 = 5;
 
 // Send message functions
 
+pub fn send_hello(net: &Res<Network<WebSocketProvider>>) {
+    let request = Hello {
+        version_number: GAME_VERSION,
+    };
+
+    match net.send_message(SERVER_CONNECTION_ID, request) {
+        Ok(_) => info!("Sent hello with version {}", GAME_VERSION),
+        Err(e) => error!("Failed to send message: {:?}", e),
+    }
+}
+
 pub fn send_random_room_request(username: &str, net: &Res<Network<WebSocketProvider>>) {
     let request = RoomJoinRequest {
         username: username.to_string(),
         room_code: "".to_string(),
+        join_type: RoomJoinType::Public,
+        version_number: GAME_VERSION,
     };
 
     match net.send_message(SERVER_CONNECTION_ID, request) {
@@ -31,6 +51,20 @@ pub fn send_random_room_request(username: &str, net: &Res<Network<WebSocketProvi
     }
 }
 
+pub fn send_create_private_room_request(username: &str, net: &Res<Network<WebSocketProvider>>) {
+    let request = RoomJoinRequest {
+        username: username.to_string(),
+        room_code: "".to_string(),
+        join_type: RoomJoinType::CreatePrivate,
+        version_number: GAME_VERSION,
+    };
+
+    match net.send_message(SERVER_CONNECTION_ID, request) {
+        Ok(_) => info!("Sent create private room request"),
+        Err(e) => error!("Failed to send message: {:?}", e),
+    }
+}
+
 pub fn send_private_room_request(
     username: &str,
     room_code: &str,
@@ -39,10 +73,44 @@ pub fn send_private_room_request(
     let request = RoomJoinRequest {
         username: username.to_string(),
         room_code: room_code.to_string(),
+        join_type: RoomJoinType::JoinPrivate,
+        version_number: GAME_VERSION,
     };
 
     match net.send_message(SERVER_CONNECTION_ID, request) {
-        Ok(_) => info!("Sent random room request"),
+        Ok(_) => info!("Sent private room request"),
+        Err(e) => error!("Failed to send message: {:?}", e),
+    }
+}
+
+pub fn send_room_rejoin_request(session_token: &str, net: &Res<Network<WebSocketProvider>>) {
+    let request = RoomRejoinRequest {
+        session_token: session_token.to_string(),
+    };
+
+    match net.send_message(SERVER_CONNECTION_ID, request) {
+        Ok(_) => info!("Sent room rejoin request"),
+        Err(e) => error!("Failed to send message: {:?}", e),
+    }
+}
+
+pub fn send_clock_sync_request(
+    rpc_client: &mut RpcClient<ClockSyncRequest>,
+    time: &Time<Real>,
+    net: &Res<Network<WebSocketProvider>>,
+) {
+    let request = ClockSyncRequest {
+        client_time_ms: time.elapsed().as_millis() as i64,
+    };
+
+    match rpc_client.call(
+        request,
+        Duration::from_secs(CLOCK_SYNC_TIMEOUT_SECS),
+        SERVER_CONNECTION_ID,
+        time,
+        net,
+    ) {
+        Ok(request_id) => info!("Sent clock sync request {}", request_id),
         Err(e) => error!("Failed to send message: {:?}", e),
     }
 }
@@ -56,6 +124,31 @@ pub fn send_start_game_request(room_id: u32, net: Res<Network<WebSocketProvider>
     }
 }
 
+pub fn send_room_settings_request(room_settings: &RoomSettings, net: &Res<Network<WebSocketProvider>>) {
+    match net.send_message(SERVER_CONNECTION_ID, room_settings.clone()) {
+        Ok(_) => info!("Sent room settings request"),
+        Err(e) => error!("Failed to send message: {:?}", e),
+    }
+}
+
+pub fn send_loan_request(
+    room_id: u32,
+    action: LoanAction,
+    amount: u32,
+    net: &Res<Network<WebSocketProvider>>,
+) {
+    let request = LoanRequest {
+        room_id,
+        action,
+        amount,
+    };
+
+    match net.send_message(SERVER_CONNECTION_ID, request) {
+        Ok(_) => info!("Sent loan request"),
+        Err(e) => error!("Failed to send message: {:?}", e),
+    }
+}
+
 pub fn send_completed_prompt(
     prompt_info_data: &mut PromptInfoDataRequest,
     prompt_index: usize,
@@ -69,12 +162,12 @@ pub fn send_completed_prompt(
     }
 }
 
-pub fn send_bid_action(requestor_player_id: u32, room_id: u32, net: &Network<WebSocketProvider>) {
+pub fn send_bid_action(requestor_player_id: PlayerId, room_id: u32, net: &Network<WebSocketProvider>) {
     match net.send_message(
         SERVER_CONNECTION_ID,
         GameActionRequest {
             requestor_player_id,
-            target_player_id: 0,
+            target_player_id: PlayerId::default(),
             room_id,
             action: GameAction::Bid,
         },
@@ -85,7 +178,7 @@ pub fn send_bid_action(requestor_player_id: u32, room_id: u32, net: &Network<Web
 }
 
 // pub fn send_end_round_action(
-//     requestor_player_id: u32,
+//     requestor_player_id: PlayerId,
 //     room_id: u32,
 //     net: &Network<WebSocketProvider>,
 // ) {
@@ -93,7 +186,7 @@ pub fn send_bid_action(requestor_player_id: u32, room_id: u32, net: &Network<Web
 //         SERVER_CONNECTION_ID,
 //         GameActionRequest {
 //             requestor_player_id,
-//             target_player_id: 0,
+//             target_player_id: PlayerId::default(),
 //             room_id,
 //             action: GameAction::EndRound,
 //         },
@@ -104,8 +197,8 @@ pub fn send_bid_action(requestor_player_id: u32, room_id: u32, net: &Network<Web
 // }
 
 pub fn send_force_bid_action(
-    requestor_player_id: u32,
-    target_player_id: u32,
+    requestor_player_id: PlayerId,
+    target_player_id: PlayerId,
     room_id: u32,
     net: &Network<WebSocketProvider>,
 ) {
@@ -130,12 +223,10 @@ fn room_state_response(
     mut query: Query<&mut RoomState>,
     mut commands: Commands,
     state: Res<State<GameState>>,
-    mut current_player_data: ResMut<CurrentPlayerData>,
     mut next_state: ResMut<NextState<GameState>>,
 ) {
     for new_message in new_messages.read() {
         info!("Received new room state message: {:?}", new_message);
-        let updated_players = &new_message.players;
 
         if state.get() != &new_message.game_state {
             next_state.set(new_message.game_state.clone());
@@ -148,11 +239,6 @@ fn room_state_response(
         }
 
         // Else create a new entity with room state
-
-        // Find the player id of the current player, the last player added to the list
-        let player_id = updated_players.last().unwrap().id;
-        *current_player_data = CurrentPlayerData { player_id };
-
         commands.spawn(new_message.additional_clone());
     }
 }
@@ -171,6 +257,11 @@ fn prompt_info_response(
             if let Some(prompt_index) = new_message.front_end_prompt_index {
                 if front_end_prompt_list.prompt_data_list.get(prompt_index).is_some() {
                     front_end_prompt_list.prompt_data_list[prompt_index] = new_message.additional_clone();
+                } else if prompt_index == front_end_prompt_list.prompt_data_list.len() {
+                    // Prompts resent after a reconnect arrive in order
+                    front_end_prompt_list
+                        .prompt_data_list
+                        .push(new_message.additional_clone());
                 } else {
                     error!("Prompt not found when accessing index");
                 }
@@ -181,6 +272,107 @@ fn prompt_info_response(
     }
 }
 
+fn hello_response(
+    mut new_messages: EventReader<NetworkData<HelloResponse>>,
+    mut server_handshake: ResMut<ServerHandshake>,
+    mut reconnect_timer: ResMut<ReconnectTimer>,
+) {
+    for new_message in new_messages.read() {
+        info!("Received new hello response: {:?}", new_message);
+        if new_message.accepted {
+            server_handshake.rejected_server_version = None;
+        } else {
+            // Reconnecting won't help, the player needs to load the new client
+            server_handshake.rejected_server_version = Some(new_message.server_version);
+            reconnect_timer.0.pause();
+        }
+    }
+}
+
+fn room_join_response(
+    mut new_messages: EventReader<NetworkData<RoomJoinResponse>>,
+    mut room_join_status: ResMut<RoomJoinStatus>,
+) {
+    for new_message in new_messages.read() {
+        info!("Received new room join response: {:?}", new_message);
+
+        // We clicked join again before the first one came back, the first join still stands
+        if new_message.result == Err(RoomJoinRejection::AlreadyInRoom) {
+            continue;
+        }
+
+        room_join_status.rejection = new_message.result.clone().err();
+    }
+}
+
+fn player_session_response(
+    mut new_messages: EventReader<NetworkData<PlayerSessionInfo>>,
+    query: Query<Entity, With<RoomState>>,
+    mut commands: Commands,
+    mut player_session: ResMut<PlayerSession>,
+    mut current_player_data: ResMut<CurrentPlayerData>,
+    mut front_end_prompt_list: ResMut<FrontEndPromptList>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for new_message in new_messages.read() {
+        info!("Received new player session message: {:?}", new_message);
+
+        match new_message.status {
+            SessionStatus::New => {
+                player_session.session_token = new_message.session_token.clone();
+                *current_player_data = CurrentPlayerData {
+                    player_id: new_message.player_id,
+                };
+            }
+            SessionStatus::Resumed => {
+                player_session.session_token = new_message.session_token.clone();
+                *current_player_data = CurrentPlayerData {
+                    player_id: new_message.player_id,
+                };
+
+                // The server resends our prompts after this
+                front_end_prompt_list.prompt_data_list.clear();
+            }
+            SessionStatus::Expired => {
+                // Our seat is gone, go back to the intro screen to join again
+                player_session.session_token.clear();
+                front_end_prompt_list.prompt_data_list.clear();
+                for entity in query.iter() {
+                    commands.entity(entity).despawn();
+                }
+                next_state.set(GameState::Intro);
+            }
+        }
+    }
+}
+
+fn clock_sync_response(
+    mut rpc_results: EventReader<RpcResult<ClockSyncRequest>>,
+    mut server_clock: ResMut<ServerClock>,
+    time: Res<Time<Real>>,
+) {
+    for rpc_result in rpc_results.read() {
+        let clock_sync_response = match &rpc_result.result {
+            Ok(clock_sync_response) => clock_sync_response,
+            Err(e) => {
+                // The next resync will try again
+                warn!("Clock sync request {} failed: {:?}", rpc_result.request_id, e);
+                continue;
+            }
+        };
+
+        server_clock.add_sample(
+            clock_sync_response.client_time_ms,
+            clock_sync_response.server_time_ms,
+            time.elapsed().as_millis() as i64,
+        );
+        info!(
+            "Synced server clock, offset {:?}ms with a {}ms round trip",
+            server_clock.offset_ms, server_clock.round_trip_ms
+        );
+    }
+}
+
 fn round_end_info_response(
     mut new_messages: EventReader<NetworkData<RoundEndInfo>>,
     mut round_end_info_data: ResMut<RoundEndInfo>,
@@ -204,39 +396,46 @@ fn game_end_info_response(
 fn game_player_notification_response(
     mut new_messages: EventReader<NetworkData<GamePlayerNotificationRequest>>,
     mut commands: Commands,
-    // mut timer: ResMut<RoundTimer>,
 ) {
+    // Late bids extend the round on the server, the new deadline comes through in the RoomState
     for new_message in new_messages.read() {
         info!("Received new round end info message: {:?}", new_message);
         commands.spawn(new_message.get_notification());
-
-        match new_message.action {
-            GameAction::Bid => {
-                // let current_duration = timer.0.duration().as_secs_f32();
-                // if timer.0.remaining_secs() < BID_INCREASE_TIMER_START_WINDOW {
-                //     timer.0.set_duration(Duration::from_secs(
-                //         (current_duration + BID_INCREASE_TIMER_VALUE) as u64,
-                //     ));
-                // }
-            }
-            GameAction::EndRound => {}
-            GameAction::ForceBid => {}
-        }
     }
 }
 
 // Etc. functions
 
-fn handle_network_events(mut new_network_events: EventReader<NetworkEvent>) {
+fn handle_network_events(
+    mut new_network_events: EventReader<NetworkEvent>,
+    mut reconnect_timer: ResMut<ReconnectTimer>,
+    player_session: Res<PlayerSession>,
+    server_handshake: Res<ServerHandshake>,
+    mut clock_sync_client: ResMut<RpcClient<ClockSyncRequest>>,
+    time: Res<Time<Real>>,
+    net: Res<Network<WebSocketProvider>>,
+) {
     for event in new_network_events.read() {
         info!("Received event");
         match event {
             NetworkEvent::Connected(conn_id) => {
                 info!("Connected to server with id: {}", conn_id);
+                reconnect_timer.0.pause();
+                send_hello(&net);
+                send_clock_sync_request(&mut clock_sync_client, &time, &net);
+
+                if !player_session.session_token.is_empty() {
+                    send_room_rejoin_request(&player_session.session_token, &net);
+                }
             }
 
             NetworkEvent::Disconnected(_) => {
                 info!("Disconnected from server!");
+                if server_handshake.rejected_server_version.is_some() {
+                    continue;
+                }
+                reconnect_timer.0.reset();
+                reconnect_timer.0.unpause();
             }
             NetworkEvent::Error(err) => {
                 error!("Error: {:?}", err);
@@ -245,25 +444,96 @@ fn handle_network_events(mut new_network_events: EventReader<NetworkEvent>) {
     }
 }
 
+fn get_server_url() -> url::Url {
+    let connect_string = if LOCAL_CONNECTION_MODE {
+        "ws://127.0.0.1:8000/ws"
+    } else {
+        "wss://artbabo-bub2g5b5e3awg3gp.eastus-01.azurewebsites.net/ws"
+    };
+
+    url::Url::parse(connect_string).unwrap()
+}
+
+// Image urls starting with a / are files served by the backend, like the offline generator's images
+pub fn resolve_image_url(image_url: &str) -> String {
+    if !image_url.starts_with('/') {
+        return image_url.to_string();
+    }
+
+    let mut server_url = get_server_url();
+    let http_scheme = if server_url.scheme() == "wss" { "https" } else { "http" };
+    if server_url.set_scheme(http_scheme).is_err() {
+        return image_url.to_string();
+    }
+
+    match server_url.join(image_url) {
+        Ok(resolved_url) => resolved_url.to_string(),
+        Err(_) => image_url.to_string(),
+    }
+}
+
 fn setup_networking(
     net: ResMut<Network<WebSocketProvider>>,
     settings: Res<NetworkSettings>,
     task_pool: Res<EventworkRuntime<TaskPool>>,
 ) {
+    let server_url = get_server_url();
 
-    let connect_string = if LOCAL_CONNECTION_MODE {
-        "ws://127.0.0.1:8000/ws"
-    } else {
-        "wss://artbabo-bub2g5b5e3awg3gp.eastus-01.azurewebsites.net/ws"
-    };
+    info!("Setting up networking and wanting to connect at {}", server_url);
+
+    net.connect(server_url, &task_pool.0, &settings);
+}
+
+// Keep trying to get back to the server after losing the connection
+fn reconnect_to_server(
+    time: Res<Time>,
+    mut reconnect_timer: ResMut<ReconnectTimer>,
+    net: ResMut<Network<WebSocketProvider>>,
+    settings: Res<NetworkSettings>,
+    task_pool: Res<EventworkRuntime<TaskPool>>,
+) {
+    reconnect_timer.0.tick(time.delta());
+
+    if !reconnect_timer.0.just_finished() {
+        return;
+    }
+
+    if net.has_connections() {
+        reconnect_timer.0.pause();
+        return;
+    }
+
+    info!("Trying to reconnect to the server");
+    net.connect(get_server_url(), &task_pool.0, &settings);
+}
 
-    info!("Setting up networking and wanting to connect at {}", connect_string);
+// A new connection could be to a restarted server, so start the clock estimate over
+fn reset_server_clock_on_connect(
+    mut new_network_events: EventReader<NetworkEvent>,
+    mut server_clock: ResMut<ServerClock>,
+) {
+    for event in new_network_events.read() {
+        if let NetworkEvent::Connected(_) = event {
+            server_clock.reset();
+        }
+    }
+}
+
+// Keep refining our estimate of the server's clock while connected
+fn resync_server_clock(
+    mut clock_sync_client: ResMut<RpcClient<ClockSyncRequest>>,
+    time: Res<Time<Real>>,
+    net: Res<Network<WebSocketProvider>>,
+) {
+    if net.has_connections() {
+        send_clock_sync_request(&mut clock_sync_client, &time, &net);
+    }
+}
 
-    net.connect(
-        url::Url::parse(connect_string).unwrap(),
-        &task_pool.0,
-        &settings,
-    );
+fn paused_reconnect_timer() -> Timer {
+    let mut timer = Timer::from_seconds(RECONNECT_INTERVAL_SECS, TimerMode::Repeating);
+    timer.pause();
+    timer
 }
 
 pub fn add_backend_server_connections(app: &mut App) {
@@ -275,16 +545,70 @@ pub fn add_backend_server_connections(app: &mut App) {
             TaskPoolBuilder::new().num_threads(2).build(),
         ))
         .insert_resource(NetworkSettings::default())
+        .insert_resource(PlayerSession::default())
+        .insert_resource(RoomJoinStatus::default())
+        .insert_resource(ServerHandshake::default())
+        .insert_resource(ReconnectTimer(paused_reconnect_timer()))
         .add_systems(Update, handle_network_events)
+        .add_systems(Update, reset_server_clock_on_connect)
+        .add_systems(Update, reconnect_to_server)
+        .add_systems(
+            Update,
+            resync_server_clock.run_if(on_timer(Duration::from_secs(CLOCK_SYNC_INTERVAL_SECS))),
+        )
         .add_systems(Startup, setup_networking)
+        .listen_for_message::<HelloResponse, WebSocketProvider>()
+        .add_systems(Update, hello_response)
+        .add_systems(Update, clock_sync_response)
+        .listen_for_message::<RoomJoinResponse, WebSocketProvider>()
+        .add_systems(Update, room_join_response)
+        .listen_for_message::<PlayerSessionInfo, WebSocketProvider>()
+        .add_systems(Update, player_session_response)
         .listen_for_message::<RoomState, WebSocketProvider>()
         .add_systems(Update, room_state_response)
         .listen_for_message::<PromptInfoDataRequest, WebSocketProvider>()
-        .add_systems(Update, prompt_info_response)
+        // A resumed session clears the prompt list, the resent prompts have to land after that
+        .add_systems(Update, prompt_info_response.after(player_session_response))
         .listen_for_message::<RoundEndInfo, WebSocketProvider>()
         .add_systems(Update, round_end_info_response)
         .listen_for_message::<GameEndInfo, WebSocketProvider>()
         .add_systems(Update, game_end_info_response)
         .listen_for_message::<GamePlayerNotificationRequest, WebSocketProvider>()
         .add_systems(Update, game_player_notification_response);
+
+    // Listening for replies needs the Network resource the plugin above inserts
+    add_rpc_method::<ClockSyncRequest>(app);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backend_server_connections_build_headless() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins);
+
+        add_backend_server_connections(&mut app);
+        app.finish();
+        app.cleanup();
+
+        assert!(app.world().contains_resource::<Network<WebSocketProvider>>());
+        assert!(app
+            .world()
+            .contains_resource::<RpcClient<ClockSyncRequest>>());
+    }
+
+    #[test]
+    fn resolve_image_url_points_backend_files_at_the_server() {
+        let resolved_url = resolve_image_url("/offline_art/art_1.png");
+        assert!(resolved_url.starts_with("http"));
+        assert!(resolved_url.ends_with("/offline_art/art_1.png"));
+        assert!(!resolved_url.contains("/ws/"));
+
+        assert_eq!(
+            resolve_image_url("https://example.com/image.png"),
+            "https://example.com/image.png"
+        );
+    }
 }
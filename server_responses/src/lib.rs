@@ -1,32 +1,168 @@
 use bevy::prelude::*;
-use bevy_eventwork::ConnectionId;
 use bevy_eventwork::NetworkMessage;
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde::Serialize;
 
-pub const DEBUG_MODE: bool = false;
 pub const LOCAL_CONNECTION_MODE: bool = false;
-pub const GAME_VERSION: u8 = 3;
-
-pub const BIDDING_ROUND_TIME: u64 = 50;
-pub const BIDDING_ROUND_END_TIME: u64 = 9;
-pub const END_SCORE_SCREEN_TIME: u64 = 30;
+pub const GAME_VERSION: u8 = 16;
 
 pub const NOTIFICATION_LIFETIME: f32 = 3.0;
 
-pub const MIN_ART_VALUE: u32 = 100;
-pub const MAX_ART_VALUE: u32 = 3500;
+// Game tuning values, loaded by the backend at startup and sent to clients inside RoomState
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameConfig {
+    pub debug_mode: bool,
+    pub bidding_round_time: u64,
+    pub bidding_round_end_time: u64,
+    pub end_score_screen_time: u64,
+    pub loan_round_time: u64,
+    // Interest added on top of a loan when it's taken out, 0.25 means borrowing 100 costs 125
+    pub loan_interest_rate: f32,
+    pub max_debt: u32,
+    pub min_art_value: u32,
+    pub max_art_value: u32,
+    pub bid_increase_amount: u32,
+    pub bid_increase_timer_value: f32,
+    pub bid_increase_timer_start_window: f32,
+    pub starting_money: i32,
+    pub force_bids_per_player: u32,
+    pub max_players: usize,
+    pub min_players: usize,
+    pub image_gen_timeout_secs: u64,
+    pub prompt_gen_timeout_secs: u64,
+    pub reconnect_grace_secs: u64,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            debug_mode: false,
+            bidding_round_time: 50,
+            bidding_round_end_time: 9,
+            end_score_screen_time: 30,
+            loan_round_time: 15,
+            loan_interest_rate: 0.25,
+            max_debt: 3000,
+            min_art_value: 100,
+            max_art_value: 3500,
+            bid_increase_amount: 100,
+            bid_increase_timer_value: 5.0,
+            bid_increase_timer_start_window: 30.0,
+            starting_money: 3000,
+            force_bids_per_player: 2,
+            max_players: 8,
+            min_players: 2,
+            image_gen_timeout_secs: 10,
+            prompt_gen_timeout_secs: 1,
+            reconnect_grace_secs: 60,
+        }
+    }
+}
+
+impl GameConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.min_art_value >= self.max_art_value {
+            return Err(format!(
+                "min_art_value ({}) must be lower than max_art_value ({})",
+                self.min_art_value, self.max_art_value
+            ));
+        }
+
+        if self.min_players < 2 || self.min_players > self.max_players {
+            return Err(format!(
+                "min_players ({}) must be at least 2 and no more than max_players ({})",
+                self.min_players, self.max_players
+            ));
+        }
+
+        if self.bidding_round_time == 0 {
+            return Err("bidding_round_time must be greater than 0".to_string());
+        }
+
+        if self.bid_increase_amount == 0 {
+            return Err("bid_increase_amount must be greater than 0".to_string());
+        }
+
+        if self.loan_interest_rate < 0.0 {
+            return Err("loan_interest_rate can't be negative".to_string());
+        }
 
-pub const BID_INCREASE_TIMER_VALUE: f32 = 5.0;
-pub const BID_INCREASE_TIMER_START_WINDOW: f32 = 30.0;
+        Ok(())
+    }
+}
 
-pub const MAX_PLAYERS: usize = 8;
-pub const MIN_PLAYERS: usize = 2;
-pub const IMAGE_GEN_TIMEOUT_SECS : u64 = 10;
-pub const PROMPT_GEN_TIMEOUT_SECS : u64 = 1;
+// Per room settings the host can change in the waiting room, defaults come from the GameConfig
+#[derive(Debug, Event, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct RoomSettings {
+    pub room_id: u32,
+    // None picks the number of prompts based on player count when the game starts
+    pub prompts_per_player: Option<u32>,
+    pub bidding_round_time: u64,
+    pub starting_money: i32,
+    pub force_bids_per_player: u32,
+    pub min_art_value: u32,
+    pub max_art_value: u32,
+}
+
+impl NetworkMessage for RoomSettings {
+    const NAME: &'static str = "RoomSettings";
+}
+
+impl HasRoomId for RoomSettings {
+    fn room_id(&self) -> u32 {
+        self.room_id
+    }
+}
 
-#[derive(Component, Resource)]
-pub struct RoundTimer(pub Timer);
+impl RoomSettings {
+    pub fn from_config(room_id: u32, config: &GameConfig) -> Self {
+        Self {
+            room_id,
+            prompts_per_player: None,
+            bidding_round_time: config.bidding_round_time,
+            starting_money: config.starting_money,
+            force_bids_per_player: config.force_bids_per_player,
+            min_art_value: config.min_art_value,
+            max_art_value: config.max_art_value,
+        }
+    }
+
+    pub fn additional_clone(&self) -> Self {
+        self.clone()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(prompts_per_player) = self.prompts_per_player {
+            if !(1..=5).contains(&prompts_per_player) {
+                return Err("Prompts per player must be between 1 and 5".to_string());
+            }
+        }
+
+        if !(10..=300).contains(&self.bidding_round_time) {
+            return Err("Bidding time must be between 10 and 300 seconds".to_string());
+        }
+
+        if !(100..=100_000).contains(&self.starting_money) {
+            return Err("Starting money must be between 100 and 100000".to_string());
+        }
+
+        if self.force_bids_per_player > 10 {
+            return Err("Force bids per player can't be more than 10".to_string());
+        }
+
+        if self.min_art_value == 0 || self.min_art_value >= self.max_art_value {
+            return Err("Minimum art value must be above 0 and lower than the maximum".to_string());
+        }
+
+        if self.max_art_value > 100_000 {
+            return Err("Maximum art value can't be more than 100000".to_string());
+        }
+
+        Ok(())
+    }
+}
 
 pub trait HasRoomId {
     fn room_id(&self) -> u32;
@@ -41,27 +177,95 @@ pub enum GameState {
     ImageCreation,
     BiddingRound,
     BiddingRoundEnd,
+    LoanRound,
     EndScoreScreen,
 }
 
+// Issued by the server when a player joins, stays the same when their connection changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+pub struct PlayerId(pub u64);
+
+impl std::fmt::Display for PlayerId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum ConnectionStatus {
+    #[default]
+    Connected,
+    Reconnecting,
+}
+
+// A piece of art a player won in a bidding round
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ArtPiece {
+    pub prompt_text: String,
+    pub prompt_answer: String,
+    pub image_url: String,
+    pub price_paid: u32,
+    pub art_value: u32,
+    pub profit: i32,
+}
+
+impl ArtPiece {
+    pub fn new(prompt_info: &PromptInfoData, price_paid: u32) -> Self {
+        Self {
+            prompt_text: prompt_info.prompt_text.clone(),
+            prompt_answer: prompt_info.prompt_answer.clone(),
+            image_url: prompt_info.image_url.clone(),
+            price_paid,
+            art_value: prompt_info.art_value,
+            profit: prompt_info.art_value as i32 - price_paid as i32,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Player {
     pub username: String,
     pub money: i32,
-    pub id: u32,
+    pub id: PlayerId,
     pub force_bids_left: u32,
     pub hints: Vec<String>,
+    pub art_collection: Vec<ArtPiece>,
+    // What the player owes including interest, taken off their money at the end of the game
+    pub debt: u32,
+    pub connection_status: ConnectionStatus,
+    // Server only values used to route messages and resume a dropped player's session
+    #[serde(skip)]
+    pub connection_id: Option<usize>,
+    #[serde(skip)]
+    pub session_token: String,
+    #[serde(skip)]
+    pub disconnect_count: u32,
+    #[serde(skip)]
+    pub prompt_requests: Vec<PromptInfoDataRequest>,
 }
 
 // Make a constructor for Player with a string input
 impl Player {
-    pub fn new(id: u32, username: String) -> Self {
+    pub fn new(id: PlayerId, username: String, settings: &RoomSettings) -> Self {
         Self {
             username,
-            money: 3000,
+            money: settings.starting_money,
             id,
-            force_bids_left: 2,
+            force_bids_left: settings.force_bids_per_player,
             hints: Vec::new(),
+            ..Default::default()
+        }
+    }
+
+    // Keep track of the latest version of each prompt sent to the player so it can be resent on reconnect
+    pub fn record_prompt_request(&mut self, prompt_info_data_request: &PromptInfoDataRequest) {
+        match prompt_info_data_request.front_end_prompt_index {
+            Some(index) if index < self.prompt_requests.len() => {
+                self.prompt_requests[index] = prompt_info_data_request.clone();
+            }
+            _ => {
+                self.prompt_requests.push(prompt_info_data_request.clone());
+            }
         }
     }
 }
@@ -70,7 +274,7 @@ impl Player {
 pub struct ArtBidInfo {
     pub prompt_info: PromptInfoData,
     pub max_bid: u32,
-    pub max_bid_player_id: u32,
+    pub max_bid_player_id: PlayerId,
     pub bid_increase_amount: u32,
 }
 
@@ -118,6 +322,8 @@ impl RoundEndInfo {
 pub struct GameEndPlayerInfo {
     pub username: String,
     pub money: i32,
+    pub debt: u32,
+    pub art_collection: Vec<ArtPiece>,
 }
 
 #[derive(Debug, Event, Clone, Serialize, Deserialize, Resource, Default)]
@@ -146,7 +352,16 @@ pub struct RoomState {
     pub remaining_prompts: Vec<PromptInfoData>,
     pub used_prompts: Vec<PromptInfoData>,
     pub room_code: String,
+    // Public rooms are filled by matchmaking, private rooms can only be joined with their code
+    pub is_public: bool,
     pub version_number: u8,
+    pub config: GameConfig,
+    pub settings: RoomSettings,
+    // Server time in milliseconds since the unix epoch when the current phase ends, if it's timed
+    pub phase_deadline_ms: Option<i64>,
+    // Bumped whenever the phase timer changes so older timers know they're stale
+    #[serde(skip)]
+    pub phase_generation: u64,
 }
 
 impl NetworkMessage for RoomState {
@@ -187,7 +402,10 @@ impl RoomState {
                 Some(player) => {
                     player.money +=
                         self.current_art_bid.prompt_info.art_value as i32 - self.current_art_bid.max_bid as i32;
-                    // TODO: Add art to player's collection
+                    player.art_collection.push(ArtPiece::new(
+                        &self.current_art_bid.prompt_info,
+                        self.current_art_bid.max_bid,
+                    ));
                     round_end_info.bid_winner_name = player.username.clone();
                 }
                 None => {
@@ -240,7 +458,7 @@ impl RoomState {
         if !self.remaining_prompts.is_empty() {
             // Prepare the next bid info
             self.current_art_bid = ArtBidInfo::default();
-            self.current_art_bid.bid_increase_amount = 100;
+            self.current_art_bid.bid_increase_amount = self.config.bid_increase_amount;
 
             // Prepare next round info
             // Choose a random prompt
@@ -264,8 +482,8 @@ impl RoomState {
 
     pub fn player_force_bid(
         &mut self,
-        requestor_id: u32,
-        target_id: u32,
+        requestor_id: PlayerId,
+        target_id: PlayerId,
     ) -> Option<GamePlayerNotificationRequest> {
         let requestor = match self
             .players
@@ -324,7 +542,7 @@ impl RoomState {
         }
     }
 
-    pub fn player_bid(&mut self, player_id: u32) -> Option<GamePlayerNotificationRequest> {
+    pub fn player_bid(&mut self, player_id: PlayerId) -> Option<GamePlayerNotificationRequest> {
         let player = match self
             .players
             .iter_mut()
@@ -362,22 +580,159 @@ impl RoomState {
         });
     }
 
-    pub fn disconnect_player(&mut self, player_id: ConnectionId) {
-        let player_index = self
+    pub fn player_loan(
+        &mut self,
+        player_id: PlayerId,
+        action: &LoanAction,
+        amount: u32,
+    ) -> Result<(), String> {
+        if self.game_state != GameState::LoanRound {
+            return Err("Loans can only be taken out between rounds".to_string());
+        }
+
+        if amount == 0 {
+            return Err("Loan amount must be greater than 0".to_string());
+        }
+
+        let interest_rate = self.config.loan_interest_rate;
+        let max_debt = self.config.max_debt;
+
+        let player = match self.players.iter_mut().find(|player| player.id == player_id) {
+            Some(player) => player,
+            None => return Err(format!("Couldn't find requested player id: {}", player_id)),
+        };
+
+        match action {
+            LoanAction::Borrow => {
+                let max_debt_error =
+                    format!("{} can't owe more than {}", player.username, max_debt);
+
+                // The amount comes from the client, so check it before doing any math with it
+                if amount > max_debt {
+                    return Err(max_debt_error);
+                }
+
+                let added_debt = (amount as f64 * (1.0 + interest_rate as f64)).ceil() as u64;
+                let new_debt = match (player.debt as u64).checked_add(added_debt) {
+                    Some(new_debt) if new_debt <= max_debt as u64 => new_debt as u32,
+                    _ => return Err(max_debt_error),
+                };
+
+                let new_money = match i32::try_from(amount)
+                    .ok()
+                    .and_then(|amount| player.money.checked_add(amount))
+                {
+                    Some(new_money) => new_money,
+                    None => return Err(format!("{} can't hold that much money", player.username)),
+                };
+
+                player.money = new_money;
+                player.debt = new_debt;
+            }
+            LoanAction::Repay => {
+                let repay_amount = amount.min(player.debt);
+
+                if player.money < repay_amount as i32 {
+                    return Err(format!(
+                        "{} doesn't have {} to repay",
+                        player.username, repay_amount
+                    ));
+                }
+
+                player.money -= repay_amount as i32;
+                player.debt -= repay_amount;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Bids near the end of a bidding round push the deadline back so there's time to respond,
+    // returns the new deadline if the bid was late enough
+    pub fn late_bid_deadline(&self, now_ms: i64) -> Option<i64> {
+        if self.game_state != GameState::BiddingRound {
+            return None;
+        }
+
+        let deadline_ms = self.phase_deadline_ms?;
+
+        let start_window_ms = (self.config.bid_increase_timer_start_window * 1000.0) as i64;
+        if deadline_ms - now_ms > start_window_ms {
+            return None;
+        }
+
+        // A bid that lands after the deadline but before the timer fires still gets the full extension
+        let extension_ms = (self.config.bid_increase_timer_value * 1000.0) as i64;
+        Some(deadline_ms.max(now_ms) + extension_ms)
+    }
+
+    // Marks the player as reconnecting, returning their session token and disconnect count
+    pub fn mark_player_disconnected(&mut self, player_id: PlayerId) -> Option<(String, u32)> {
+        let player = self
             .players
-            .iter()
-            .position(|player| player.id == player_id.id);
+            .iter_mut()
+            .find(|player| player.id == player_id)?;
+
+        player.connection_id = None;
+        player.connection_status = ConnectionStatus::Reconnecting;
+        player.disconnect_count += 1;
+
+        Some((player.session_token.clone(), player.disconnect_count))
+    }
+
+    // Removes a player whose grace window ran out, unless they came back or dropped again since
+    pub fn remove_disconnected_player(&mut self, session_token: &str, disconnect_count: u32) -> bool {
+        let player_index = self.players.iter().position(|player| {
+            player.session_token == session_token
+                && player.connection_id.is_none()
+                && player.disconnect_count == disconnect_count
+        });
 
         match player_index {
             Some(index) => {
                 self.players.remove(index);
+                true
             }
-            None => {
-                error!("Could not find player with id {}", player_id);
-            }
+            None => false,
         }
     }
 
+    pub fn reconnect_player(&mut self, session_token: &str, connection_id: usize) -> Option<&Player> {
+        let player = self
+            .players
+            .iter_mut()
+            .find(|player| !session_token.is_empty() && player.session_token == session_token)?;
+
+        player.connection_id = Some(connection_id);
+        player.connection_status = ConnectionStatus::Connected;
+
+        Some(player)
+    }
+
+    pub fn is_username_taken(&self, username: &str) -> bool {
+        let username = username.trim();
+        self.players
+            .iter()
+            .any(|player| player.username.trim().eq_ignore_ascii_case(username))
+    }
+
+    // Checks if a new player can take a seat in this room
+    pub fn can_join(&self, username: &str) -> Result<(), RoomJoinRejection> {
+        if self.game_state != GameState::WaitingRoom {
+            return Err(RoomJoinRejection::GameInProgress);
+        }
+
+        if self.players.len() >= self.config.max_players {
+            return Err(RoomJoinRejection::RoomFull);
+        }
+
+        if self.is_username_taken(username) {
+            return Err(RoomJoinRejection::NameTaken);
+        }
+
+        Ok(())
+    }
+
     pub fn get_game_end_info(&self) -> Option<GameEndInfo> {
         let mut game_end_info = GameEndInfo {
             players: Vec::new(),
@@ -386,7 +741,9 @@ impl RoomState {
         for player in &self.players {
             game_end_info.players.push(GameEndPlayerInfo {
                 username: player.username.clone(),
-                money: player.money,
+                money: player.money - player.debt as i32,
+                debt: player.debt,
+                art_collection: player.art_collection.clone(),
             });
         }
 
@@ -396,21 +753,209 @@ impl RoomState {
         return Some(game_end_info);
     }
 
+    pub fn apply_settings(&mut self, settings: RoomSettings) -> Result<(), String> {
+        if self.game_state != GameState::WaitingRoom {
+            return Err("Settings can only be changed in the waiting room".to_string());
+        }
+
+        settings.validate()?;
+
+        // Nobody has played yet so reset everyone to the new starting values
+        for player in self.players.iter_mut() {
+            player.money = settings.starting_money;
+            player.force_bids_left = settings.force_bids_per_player;
+        }
+
+        self.settings = RoomSettings {
+            room_id: self.room_id,
+            ..settings
+        };
+
+        Ok(())
+    }
+
     pub fn get_completed_prompt_count(&self) -> u32 {
         return self.remaining_prompts.len() as u32;
     }
 }
 
+// First message a client sends after connecting, so the server can turn away clients built for
+// a different wire layout. Don't change the layout of Hello or HelloResponse, old clients need
+// to be able to read the rejection.
+#[derive(Debug, Event, Clone, Serialize, Deserialize, Default)]
+pub struct Hello {
+    pub version_number: u8,
+}
+
+impl NetworkMessage for Hello {
+    const NAME: &'static str = "Hello";
+}
+
+#[derive(Debug, Event, Clone, Serialize, Deserialize, Default)]
+pub struct HelloResponse {
+    pub server_version: u8,
+    pub accepted: bool,
+}
+
+impl NetworkMessage for HelloResponse {
+    const NAME: &'static str = "HelloResponse";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum RoomJoinType {
+    // Matchmake into any public room that's still waiting for players
+    #[default]
+    Public,
+    // Make a new private room, the server picks the code
+    CreatePrivate,
+    // Join the private room with the given room code
+    JoinPrivate,
+}
+
 #[derive(Debug, Event, Clone, Serialize, Deserialize, Default)]
 pub struct RoomJoinRequest {
     pub username: String,
     pub room_code: String,
+    pub join_type: RoomJoinType,
+    pub version_number: u8,
 }
 
 impl NetworkMessage for RoomJoinRequest {
     const NAME: &'static str = "RoomCreationRequest";
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RoomJoinRejection {
+    RoomNotFound,
+    RoomFull,
+    GameInProgress,
+    NameTaken,
+    InvalidName,
+    AlreadyInRoom,
+}
+
+impl std::fmt::Display for RoomJoinRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RoomJoinRejection::RoomNotFound => write!(f, "No room was found with that code"),
+            RoomJoinRejection::RoomFull => write!(f, "That room is full"),
+            RoomJoinRejection::GameInProgress => write!(f, "That room's game has already started"),
+            RoomJoinRejection::NameTaken => {
+                write!(f, "Someone in that room already has your username")
+            }
+            RoomJoinRejection::InvalidName => write!(f, "Usernames can't be empty"),
+            RoomJoinRejection::AlreadyInRoom => write!(f, "You're already in a room"),
+        }
+    }
+}
+
+// Tells a player whether they got into a room, with the room id or the reason they didn't
+#[derive(Debug, Event, Clone, Serialize, Deserialize)]
+pub struct RoomJoinResponse {
+    pub result: Result<u32, RoomJoinRejection>,
+}
+
+impl NetworkMessage for RoomJoinResponse {
+    const NAME: &'static str = "RoomJoinResponse";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum SessionStatus {
+    #[default]
+    New,
+    Resumed,
+    Expired,
+}
+
+// Sent to a player when they join or rejoin a room, the token lets them resume after a dropped connection
+#[derive(Debug, Event, Clone, Serialize, Deserialize, Default)]
+pub struct PlayerSessionInfo {
+    pub session_token: String,
+    pub player_id: PlayerId,
+    pub room_id: u32,
+    pub status: SessionStatus,
+}
+
+impl NetworkMessage for PlayerSessionInfo {
+    const NAME: &'static str = "PlayerSessionInfo";
+}
+
+impl PlayerSessionInfo {
+    pub fn additional_clone(&self) -> Self {
+        self.clone()
+    }
+}
+
+#[derive(Debug, Event, Clone, Serialize, Deserialize, Default)]
+pub struct RoomRejoinRequest {
+    pub session_token: String,
+}
+
+impl NetworkMessage for RoomRejoinRequest {
+    const NAME: &'static str = "RoomRejoinRequest";
+}
+
+// A request that gets exactly one reply, matched up by request id. It's sent wrapped in an
+// RpcCall and the reply comes back as an RpcReply under the response's name, so response types
+// shouldn't also be sent on their own.
+pub trait RpcMethod: NetworkMessage + Clone {
+    type Response: NetworkMessage + Clone;
+    type Error: Serialize + DeserializeOwned + Clone + Send + Sync + 'static;
+}
+
+// Same layout as event_work_server's RpcRequest and RpcResponse
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcCall<T> {
+    pub request_id: u64,
+    pub request: T,
+}
+
+impl<T: RpcMethod> NetworkMessage for RpcCall<T> {
+    const NAME: &'static str = T::NAME;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct RpcReply<T: RpcMethod> {
+    pub request_id: u64,
+    pub result: Result<T::Response, T::Error>,
+}
+
+impl<T: RpcMethod> NetworkMessage for RpcReply<T> {
+    const NAME: &'static str = T::Response::NAME;
+}
+
+// Sent by clients to work out the difference between their clock and the server's
+#[derive(Debug, Event, Clone, Serialize, Deserialize, Default)]
+pub struct ClockSyncRequest {
+    pub client_time_ms: i64,
+}
+
+impl NetworkMessage for ClockSyncRequest {
+    const NAME: &'static str = "ClockSyncRequest";
+}
+
+impl RpcMethod for ClockSyncRequest {
+    type Response = ClockSyncResponse;
+    type Error = String;
+}
+
+#[derive(Debug, Event, Clone, Serialize, Deserialize, Default)]
+pub struct ClockSyncResponse {
+    pub client_time_ms: i64,
+    pub server_time_ms: i64,
+}
+
+impl NetworkMessage for ClockSyncResponse {
+    const NAME: &'static str = "ClockSyncResponse";
+}
+
+impl ClockSyncResponse {
+    pub fn additional_clone(&self) -> Self {
+        self.clone()
+    }
+}
+
 #[derive(Debug, Event, Clone, Serialize, Deserialize, Default)]
 pub struct StartGameRequest {
     pub room_id: u32,
@@ -441,7 +986,7 @@ pub struct PromptInfoData {
     pub prompt_text: String,
     pub prompt_answer: String,
     pub image_url: String,
-    pub owner_id: u32,
+    pub owner_id: PlayerId,
     pub art_value: u32,
 }
 
@@ -481,8 +1026,8 @@ pub enum GameAction {
 #[derive(Debug, Event, Clone, Serialize, Deserialize)]
 pub struct GameActionRequest {
     pub room_id: u32,
-    pub requestor_player_id: u32,
-    pub target_player_id: u32,
+    pub requestor_player_id: PlayerId,
+    pub target_player_id: PlayerId,
     pub action: GameAction,
 }
 
@@ -496,9 +1041,33 @@ impl HasRoomId for GameActionRequest {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum LoanAction {
+    #[default]
+    Borrow,
+    Repay,
+}
+
+#[derive(Debug, Event, Clone, Serialize, Deserialize, Default)]
+pub struct LoanRequest {
+    pub room_id: u32,
+    pub action: LoanAction,
+    pub amount: u32,
+}
+
+impl NetworkMessage for LoanRequest {
+    const NAME: &'static str = "LoanRequest";
+}
+
+impl HasRoomId for LoanRequest {
+    fn room_id(&self) -> u32 {
+        self.room_id
+    }
+}
+
 #[derive(Debug, Component, Clone)]
 pub struct GamePlayerNotification {
-    pub target_player_id: u32,
+    pub target_player_id: PlayerId,
     pub message: String,
     pub action: GameAction,
     pub timer: Timer,
@@ -506,7 +1075,7 @@ pub struct GamePlayerNotification {
 
 #[derive(Debug, Event, Clone, Serialize, Deserialize)]
 pub struct GamePlayerNotificationRequest {
-    pub target_player_id: u32,
+    pub target_player_id: PlayerId,
     pub message: String,
     pub action: GameAction,
 }
@@ -533,3 +1102,165 @@ pub enum TaskCompletionStatus {
     Completed,
     Error,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW_MS: i64 = 1_000_000;
+
+    // Default config, a 30 second late bid window and 5 second extensions
+    fn bidding_room(deadline_ms: i64) -> RoomState {
+        RoomState {
+            game_state: GameState::BiddingRound,
+            phase_deadline_ms: Some(deadline_ms),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn late_bid_deadline_ignores_bids_before_the_window() {
+        let room_state = bidding_room(NOW_MS + 31_000);
+
+        assert_eq!(room_state.late_bid_deadline(NOW_MS), None);
+    }
+
+    #[test]
+    fn late_bid_deadline_extends_bids_inside_the_window() {
+        let room_state = bidding_room(NOW_MS + 10_000);
+
+        assert_eq!(room_state.late_bid_deadline(NOW_MS), Some(NOW_MS + 15_000));
+    }
+
+    #[test]
+    fn late_bid_deadline_extends_from_now_once_the_deadline_passed() {
+        let room_state = bidding_room(NOW_MS - 2_000);
+
+        assert_eq!(room_state.late_bid_deadline(NOW_MS), Some(NOW_MS + 5_000));
+    }
+
+    #[test]
+    fn late_bid_deadline_only_applies_to_bidding_rounds() {
+        let mut room_state = bidding_room(NOW_MS + 10_000);
+        room_state.game_state = GameState::LoanRound;
+
+        assert_eq!(room_state.late_bid_deadline(NOW_MS), None);
+    }
+
+    const BORROWER_ID: PlayerId = PlayerId(1);
+
+    // Default config, 25% interest and a max debt of 3000, with one player holding 3000
+    fn loan_room() -> RoomState {
+        RoomState {
+            game_state: GameState::LoanRound,
+            players: vec![Player {
+                username: "borrower".to_string(),
+                money: 3000,
+                id: BORROWER_ID,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn borrower(room_state: &RoomState) -> &Player {
+        &room_state.players[0]
+    }
+
+    #[test]
+    fn player_loan_allows_borrowing_up_to_max_debt() {
+        let mut room_state = loan_room();
+
+        // 2400 plus 25% interest is exactly the 3000 max debt
+        assert!(room_state
+            .player_loan(BORROWER_ID, &LoanAction::Borrow, 2400)
+            .is_ok());
+        assert_eq!(borrower(&room_state).money, 5400);
+        assert_eq!(borrower(&room_state).debt, 3000);
+    }
+
+    #[test]
+    fn player_loan_rejects_borrowing_past_max_debt() {
+        let mut room_state = loan_room();
+
+        // Rounds up to 3002 with interest
+        assert!(room_state
+            .player_loan(BORROWER_ID, &LoanAction::Borrow, 2401)
+            .is_err());
+        assert_eq!(borrower(&room_state).money, 3000);
+        assert_eq!(borrower(&room_state).debt, 0);
+    }
+
+    #[test]
+    fn player_loan_rejects_huge_amounts_without_overflowing() {
+        let mut room_state = loan_room();
+        assert!(room_state
+            .player_loan(BORROWER_ID, &LoanAction::Borrow, u32::MAX)
+            .is_err());
+
+        // Even when the debt limit allows it, the money can't go past i32::MAX
+        room_state.config.max_debt = u32::MAX;
+        room_state.config.loan_interest_rate = 0.0;
+        assert!(room_state
+            .player_loan(BORROWER_ID, &LoanAction::Borrow, u32::MAX)
+            .is_err());
+
+        assert_eq!(borrower(&room_state).money, 3000);
+        assert_eq!(borrower(&room_state).debt, 0);
+    }
+
+    #[test]
+    fn player_loan_repays_at_most_the_debt() {
+        let mut room_state = loan_room();
+        room_state.players[0].debt = 500;
+
+        assert!(room_state
+            .player_loan(BORROWER_ID, &LoanAction::Repay, 1000)
+            .is_ok());
+        assert_eq!(borrower(&room_state).money, 2500);
+        assert_eq!(borrower(&room_state).debt, 0);
+    }
+
+    #[test]
+    fn player_loan_rejects_repaying_without_the_money() {
+        let mut room_state = loan_room();
+        room_state.players[0].money = 100;
+        room_state.players[0].debt = 500;
+
+        assert!(room_state
+            .player_loan(BORROWER_ID, &LoanAction::Repay, 500)
+            .is_err());
+        assert_eq!(borrower(&room_state).money, 100);
+        assert_eq!(borrower(&room_state).debt, 500);
+    }
+
+    #[test]
+    fn player_loan_only_works_in_the_loan_round() {
+        let mut room_state = loan_room();
+        room_state.game_state = GameState::BiddingRound;
+
+        assert!(room_state
+            .player_loan(BORROWER_ID, &LoanAction::Borrow, 100)
+            .is_err());
+        assert_eq!(borrower(&room_state).debt, 0);
+    }
+
+    #[test]
+    fn game_end_info_takes_debt_off_the_final_money() {
+        let mut room_state = loan_room();
+        room_state.players[0].debt = 1250;
+        room_state.players.push(Player {
+            username: "saver".to_string(),
+            money: 2000,
+            id: PlayerId(2),
+            ..Default::default()
+        });
+
+        let game_end_info = room_state.get_game_end_info().unwrap();
+
+        // 3000 - 1250 puts the borrower behind the player with 2000 and no debt
+        assert_eq!(game_end_info.players[0].username, "saver");
+        assert_eq!(game_end_info.players[1].money, 1750);
+        assert_eq!(game_end_info.players[1].debt, 1250);
+    }
+}